@@ -7,6 +7,7 @@ let
     ./Cargo.toml
     ./Cargo.lock
     ./src/main.rs
+    ./calendar.html
   ];
 in
 rustPlatform.buildRustPackage {
@@ -1,23 +1,29 @@
-use chrono::{DateTime, Datelike, Duration, NaiveDate, NaiveTime, TimeZone, Timelike, Utc};
-use chrono_tz::Tz;
-use clap::{Parser, Subcommand};
-use colored::Colorize;
+use base64::Engine;
+use chrono::{DateTime, Datelike, Duration, NaiveDate, NaiveDateTime, NaiveTime, Offset, TimeZone as _, Timelike, Utc, Weekday};
+use chrono_tz::{OffsetComponents, OffsetName, Tz};
+use clap::{CommandFactory, Parser, Subcommand, ValueEnum};
+use clap_complete::{generate, Shell};
+use colored::{Color, Colorize};
 use dialoguer::{theme::ColorfulTheme, Confirm};
-use ics::properties::{Description, DtEnd, DtStart, Location, Status, Summary};
-use ics::{Event, ICalendar};
+use ics::parameters::{FBType, TzIDParam};
+use ics::properties::{Categories, Description, DtEnd, DtStart, FreeBusyTime, LastModified, Location, Sequence, Status, Summary, TzName};
+use ics::components::Property;
+use ics::{Daylight, Event, FreeBusy, ICalendar, Standard, TimeZone};
 use itertools::Itertools;
 use serde::{Deserialize, Serialize};
-use serde_json;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fs::{File, OpenOptions};
-use std::io::{Error, ErrorKind, Seek, SeekFrom};
+use std::io::{BufRead, BufReader, Error, ErrorKind, IsTerminal, Read, Seek, SeekFrom, Write};
+use std::net::{TcpListener, TcpStream};
 use std::path::PathBuf;
-use std::process::Command;
+use std::process::{Child, Command, ExitCode, Stdio};
 use std::env::var;
+#[cfg(unix)]
+use std::os::unix::process::CommandExt;
+use std::time::Instant;
 use uuid::Uuid;
 use toml::{from_str, to_string_pretty};
 use xdg::BaseDirectories;
-use iana_time_zone;
 
 const APP_NAME: &str = "plantrack";
 const DEFAULT_CONFIG_FILE: &str = "config.toml";
@@ -45,7 +51,7 @@ struct Args {
 enum Commands {
     /// Add a new event to the schedule.
     Add {
-        /// Project and task, separated by a colon. Example: "ProjectA:TaskB"
+        /// Project and task, separated by a colon, optionally followed by subtasks. Example: "ProjectA:TaskB"
         project_task: String,
 
         /// Timespan in the format HH:MM-HH:MM.
@@ -68,10 +74,22 @@ enum Commands {
         /// Mark event as booked.
         #[arg(short, long)]
         booked: bool,
+
+        /// Tag for the event, can be given multiple times. #words in the note are added as tags as well.
+        #[arg(long = "tag", value_name = "TAG")]
+        tags: Vec<String>,
+
+        /// Apply without confirmation.
+        #[arg(long, short)]
+        yes: bool,
+
+        /// Output format, json and tsv are stable formats for scripts.
+        #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+        output: OutputFormat,
     },
     /// Quickly add a new booked event for the current time.
     Quickadd {
-        /// Project and task, separated by a colon. Example: "ProjectA:TaskB"
+        /// Project and task, separated by a colon, optionally followed by subtasks. Example: "ProjectA:TaskB"
         project_task: String,
 
         /// Duration of the event in minutes. Defaults to the rounding interval.
@@ -89,10 +107,22 @@ enum Commands {
         /// Optional to apply next minutes, default is past minutes.
         #[arg(short, long)]
         forward: bool,
+
+        /// Tag for the event, can be given multiple times. #words in the note are added as tags as well.
+        #[arg(long = "tag", value_name = "TAG")]
+        tags: Vec<String>,
+
+        /// Apply without confirmation.
+        #[arg(long, short)]
+        yes: bool,
+
+        /// Output format, json and tsv are stable formats for scripts.
+        #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+        output: OutputFormat,
     },
     /// Add a todo item to the schedule.
     Todo {
-        /// Project and task, separated by a colon. Example: "ProjectA:TaskB"
+        /// Project and task, separated by a colon, optionally followed by subtasks. Example: "ProjectA:TaskB"
         project_task: String,
 
         /// Duration of the todo item in minutes. Defaults to 2 * rounding interval.
@@ -119,6 +149,10 @@ enum Commands {
         /// Optional location for the event.
         #[arg(short, long)]
         location: Option<String>,
+
+        /// Tag for the event, can be given multiple times. #words in the note are added as tags as well.
+        #[arg(long = "tag", value_name = "TAG")]
+        tags: Vec<String>,
     },
     /// List all scheduled events.
     List {
@@ -134,11 +168,23 @@ enum Commands {
         /// Show a summary of projects and their total time.
         #[arg(short, long)]
         summary: bool,
+
+        /// Only show events with this tag.
+        #[arg(long)]
+        tag: Option<String>,
+
+        /// Only show events below this project path. Example: "ProjectA" or "ProjectA:TaskB"
+        #[arg(long, value_name = "PROJECT[:TASK...]")]
+        project: Option<String>,
+
+        /// Output format, json and tsv are stable formats for scripts.
+        #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+        output: OutputFormat,
     },
     /// Generate a report for a specific project.
     Report {
-        /// The project to generate the report for.
-        project: String,
+        /// The project or project path (e.g. "ProjectA:TaskB") to generate the report for. Defaults to all projects.
+        project: Option<String>,
 
         /// Reporting month. Defaults to current month
         #[arg(short, long)]
@@ -148,9 +194,161 @@ enum Commands {
         #[arg(short, long)]
         year: Option<i32>,
 
+        /// First day of the reporting period in YYYY-MM-DD format, instead of a month.
+        #[arg(long, conflicts_with_all = ["month", "year"])]
+        from: Option<String>,
+
+        /// Last day of the reporting period in YYYY-MM-DD format. Defaults to today.
+        #[arg(long, requires = "from")]
+        to: Option<String>,
+
         /// Target time in hours for the period (e.g., 10.5 for 10 hours and 30 minutes).
         #[arg(short, long, value_name = "HOURS")]
         target: Option<f64>,
+
+        /// Only include events with this tag.
+        #[arg(long)]
+        tag: Option<String>,
+
+        /// Group the report by task, tag or location.
+        #[arg(long, value_enum, default_value_t = ReportBy::Task)]
+        by: ReportBy,
+
+        /// Output format, json and tsv are stable formats for scripts.
+        #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+        output: OutputFormat,
+    },
+    /// Show a weekly timesheet grid of project:task per day.
+    Timesheet {
+        /// Week to show, either as ISO week (YYYY-Www) or any date in the week (YYYY-MM-DD).
+        /// Defaults to the current week, also if given without a value.
+        #[arg(short, long, value_name = "WEEK", num_args = 0..=1, default_missing_value = "current")]
+        week: Option<String>,
+
+        /// Show durations as hh:mm instead of decimal hours.
+        #[arg(long)]
+        human: bool,
+
+        /// Include planned (not booked) events.
+        #[arg(short, long)]
+        all: bool,
+
+        /// Export the timesheet to a CSV file.
+        #[arg(long, value_name = "FILE")]
+        csv: Option<PathBuf>,
+    },
+    /// Show the working time account (overtime/undertime) based on the configured working hours.
+    Balance {
+        /// Last day of the balance in YYYY-MM-DD format. Defaults to today.
+        #[arg(long, short)]
+        date: Option<String>,
+
+        /// Number of weeks to show in detail (default: 4).
+        #[arg(short, long, default_value_t = 4)]
+        weeks: u32,
+
+        /// Show durations as hh:mm instead of decimal hours.
+        #[arg(long)]
+        human: bool,
+    },
+    /// Show a mileage logbook of the trips between locations.
+    Mileage {
+        /// Month of the logbook. Defaults to current month
+        #[arg(short, long)]
+        month: Option<u32>,
+
+        /// Year of the logbook. Defaults to current year
+        #[arg(short, long)]
+        year: Option<i32>,
+
+        /// First day of the logbook in YYYY-MM-DD format, instead of a month.
+        #[arg(long, conflicts_with_all = ["month", "year"])]
+        from: Option<String>,
+
+        /// Last day of the logbook in YYYY-MM-DD format. Defaults to today.
+        #[arg(long, requires = "from")]
+        to: Option<String>,
+
+        /// Add the trips from and back to the first home location on each day.
+        #[arg(long)]
+        commute: bool,
+
+        /// Export the logbook to a CSV file.
+        #[arg(long, value_name = "FILE")]
+        csv: Option<PathBuf>,
+    },
+    /// Show focus and fragmentation statistics per day.
+    Stats {
+        /// Number of days to look back (default: 7).
+        #[arg(short, long, default_value_t = 7)]
+        past_days: u32,
+
+        /// Last day of the statistics in YYYY-MM-DD format. Defaults to today.
+        #[arg(long)]
+        date: Option<String>,
+
+        /// Only include booked events.
+        #[arg(short, long)]
+        booked: bool,
+    },
+    /// Compare planned, booked and missed time per project and week.
+    Accuracy {
+        /// Number of weeks to show (default: 4).
+        #[arg(short, long, default_value_t = 4)]
+        weeks: u32,
+
+        /// Any date in the last week in YYYY-MM-DD format. Defaults to today.
+        #[arg(long)]
+        date: Option<String>,
+    },
+    /// Show charts of booked hours in the terminal.
+    Chart {
+        /// Chart to show.
+        #[arg(short, long, value_enum, default_value_t = ChartKind::All)]
+        kind: ChartKind,
+
+        /// Last day of the charts in YYYY-MM-DD format. Defaults to today.
+        #[arg(long)]
+        date: Option<String>,
+
+        /// Number of weeks for the project bars (default: 8).
+        #[arg(short, long, default_value_t = 8)]
+        weeks: u32,
+
+        /// Include planned (not booked) events.
+        #[arg(short, long)]
+        all: bool,
+    },
+    /// Export an agenda of a date range to another format.
+    Export {
+        /// Output format.
+        #[arg(short, long, value_enum)]
+        format: ExportFormat,
+
+        /// First day in YYYY-MM-DD format. Defaults to today.
+        #[arg(long)]
+        from: Option<String>,
+
+        /// Last day in YYYY-MM-DD format. Defaults to 7 days after the first day.
+        #[arg(long)]
+        to: Option<String>,
+
+        /// Write to a file instead of stdout.
+        #[arg(short, long, value_name = "FILE")]
+        output: Option<PathBuf>,
+    },
+    /// Import booked events from another time tracker.
+    Import {
+        /// Input format.
+        #[arg(short, long, value_enum)]
+        format: ImportFormat,
+
+        /// File to import, a Timewarrior data file or JSON export, a detailed CSV export or a timeclock file.
+        file: PathBuf,
+
+        /// Import without confirmation.
+        #[arg(long, short)]
+        yes: bool,
     },
     /// Check if a time slot is free.
     Free {
@@ -162,11 +360,45 @@ enum Commands {
         /// Example: "2024-03-16"
         #[arg(long, short)]
         date: Option<String>,
+
+        /// Output format, json and tsv are stable formats for scripts.
+        #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+        output: OutputFormat,
     },
     /// Show the current project:task.
-    Current {},
+    Current {
+        /// Output format, json and tsv are stable formats for scripts.
+        #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+        output: OutputFormat,
+    },
     /// Push by running a push_command if present in the config file
     Push {
+        /// Push even if the ICS files did not change since the last push.
+        #[arg(long, short)]
+        force: bool,
+        /// Show the changes not pushed yet and the last error, without pushing.
+        #[arg(long, conflicts_with = "force")]
+        status: bool,
+    },
+    /// Two-way sync with the CalDAV collection configured in the config file.
+    Sync {
+        /// Only show the changes, without syncing.
+        #[arg(long)]
+        dry_run: bool,
+
+        /// Resolve conflicts in favour of the local or the remote event.
+        #[arg(long, value_enum)]
+        prefer: Option<SyncPrefer>,
+
+        /// Apply the changes without confirmation.
+        #[arg(long, short)]
+        yes: bool,
+    },
+    /// Serve the calendar page, the live ICS feed and the events as JSON over HTTP.
+    Serve {
+        /// Address to listen on.
+        #[arg(long, default_value = "127.0.0.1:8080")]
+        bind: String,
     },
     /// Remove events older than a specified number of days.
     Cleanup {
@@ -199,6 +431,35 @@ enum Commands {
         /// Mark event as booked.
         #[arg(short, long)]
         booked: Option<bool>,
+
+        /// Add a tag to the event, can be given multiple times.
+        #[arg(long = "tag", value_name = "TAG")]
+        tags: Vec<String>,
+
+        /// Remove a tag from the event, can be given multiple times.
+        #[arg(long = "untag", value_name = "TAG")]
+        untags: Vec<String>,
+
+        /// Apply without confirmation.
+        #[arg(long, short)]
+        yes: bool,
+
+        /// Output format, json and tsv are stable formats for scripts.
+        #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+        output: OutputFormat,
+    },
+    /// Print the shell completion script, completing projects, tasks, event IDs, locations and timezones from the schedule.
+    Completions {
+        /// Shell to generate the completions for. Bash, zsh and fish complete data from the schedule.
+        shell: Shell,
+    },
+    /// Complete a value from the schedule for the completion scripts.
+    #[command(name = "__complete", hide = true)]
+    Complete {
+        shell: Shell,
+
+        /// Command line up to the cursor.
+        line: String,
     },
     /// Delete an event by ID.
     Delete {
@@ -208,9 +469,65 @@ enum Commands {
         /// Timespan in the format HH:MM-HH:MM to remove from the event.
         #[arg(short, long)]
         timespan: Option<String>,
+
+        /// Apply without confirmation.
+        #[arg(long, short)]
+        yes: bool,
+
+        /// Output format, json and tsv are stable formats for scripts.
+        #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+        output: OutputFormat,
     },
 }
 
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Default)]
+enum OutputFormat {
+    #[default]
+    Text,
+    Json,
+    Tsv,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+enum ExportFormat {
+    Org,
+    Markdown,
+    Todotxt,
+    Timewarrior,
+    Toggl,
+    Clockify,
+    Timeclock,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+enum ImportFormat {
+    Timewarrior,
+    Toggl,
+    Clockify,
+    Timeclock,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+enum SyncPrefer {
+    Local,
+    Remote,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+enum ChartKind {
+    All,
+    Heatmap,
+    Weeks,
+    Sparkline,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+enum ReportBy {
+    Task,
+    Tag,
+    Location,
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 struct ScheduleEvent {
     id: String,
@@ -222,6 +539,14 @@ struct ScheduleEvent {
     note: Option<String>,
     location: Option<String>,
     booked: bool,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    tags: Vec<String>,
+    /// Whether the event was planned before it was booked, unknown for events of older versions.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    planned_ahead: Option<bool>,
+    /// Name of the overlay calendar the event was read from, overlay events are never saved.
+    #[serde(skip)]
+    overlay: Option<String>,
 }
 
 impl PartialEq for ScheduleEvent {
@@ -236,8 +561,54 @@ impl ScheduleEvent {
             && self.note == other.note
             && self.location == other.location
             && self.booked == other.booked
+            && self.tags == other.tags
+            && self.planned_ahead == other.planned_ahead
             && self.end_time == other.start_time
     }
+
+    fn has_tag(&self, tag: &str) -> bool {
+        let tag = normalize_tag(tag);
+        self.tags.contains(&tag)
+    }
+}
+
+// Normalizes a project:task[:subtask...] path, at least project and task are required
+fn parse_project_path(project_task: &str) -> Result<String, Error> {
+    let segments: Vec<&str> = project_task.split(':').map(|segment| segment.trim()).collect();
+    if segments.len() < 2 || segments.iter().any(|segment| segment.is_empty()) {
+        return Err(Error::new(ErrorKind::InvalidInput, "Invalid project:task format"));
+    }
+    Ok(segments.join(":"))
+}
+
+// Whether a project path equals the prefix or lies below it, e.g. "A:B:C" matches "A" and "A:B"
+fn matches_path(summary: &str, prefix: &str) -> bool {
+    summary == prefix || summary.strip_prefix(prefix).is_some_and(|rest| rest.starts_with(':'))
+}
+
+fn normalize_tag(tag: &str) -> String {
+    tag.trim().trim_start_matches('#').to_lowercase()
+}
+
+// Combine explicitly given tags with #words found in the note
+fn collect_tags(existing: &[String], tags: &[String], note: Option<&str>) -> Vec<String> {
+    let note_tags = note
+        .unwrap_or_default()
+        .split_whitespace()
+        .filter(|word| word.starts_with('#'))
+        .map(|word| word.trim_end_matches(|c: char| !c.is_alphanumeric() && c != '-' && c != '_'));
+
+    let mut all_tags: Vec<String> = existing
+        .iter()
+        .map(|tag| tag.as_str())
+        .chain(tags.iter().map(|tag| tag.as_str()))
+        .chain(note_tags)
+        .map(normalize_tag)
+        .filter(|tag| !tag.is_empty())
+        .collect();
+    all_tags.sort();
+    all_tags.dedup();
+    all_tags
 }
 
 #[derive(Deserialize, Serialize, Debug)]
@@ -248,6 +619,290 @@ struct Config {
     export_notes: Option<bool>,
     rounding: Option<u32>,
     push_command: Option<String>,
+    /// Seconds until push_command is killed, defaults to 60.
+    push_timeout: Option<u64>,
+    /// Push after every change of the schedule.
+    #[serde(default)]
+    auto_push: bool,
+    ics_summary_depth: Option<usize>,
+    #[serde(default)]
+    home_locations: Vec<String>,
+    #[serde(default)]
+    locations: BTreeMap<String, LocationInfo>,
+    #[serde(default)]
+    distances: Vec<LocationDistance>,
+    working_time: Option<WorkingTime>,
+    #[serde(default)]
+    billing: HashMap<String, BillingPolicy>,
+    /// Sync state, next to the schedule file by default.
+    state_file: Option<PathBuf>,
+    caldav: Option<CaldavConfig>,
+    serve: Option<ServeConfig>,
+    freebusy: Option<FreeBusyConfig>,
+    /// ICS export rules keyed by project or project:task, "*" applies to all other events.
+    #[serde(default)]
+    export_rules: BTreeMap<String, ExportRule>,
+    /// Days in the past to export to the ICS file, defaults to 7.
+    ics_past_days: Option<i64>,
+    /// Days in the future to export to the ICS file, all future events by default.
+    ics_future_days: Option<i64>,
+    /// X-WR-CALNAME of the ICS file, defaults to plantrack.
+    ics_calendar_name: Option<String>,
+    /// Export local times with this TZID and a VTIMEZONE instead of UTC.
+    ics_timezone: Option<String>,
+    /// Read-only ICS calendars by name, their events count as busy but are never written.
+    #[serde(default)]
+    overlays: BTreeMap<String, PathBuf>,
+    /// Additional ICS files with their own window, filters and export rules.
+    #[serde(default)]
+    feeds: BTreeMap<String, FeedConfig>,
+    hooks: Option<HooksConfig>,
+}
+
+// Commands run with sh -c, receiving the changes as JSON on stdin
+#[derive(Deserialize, Serialize, Debug)]
+struct HooksConfig {
+    on_add: Option<String>,
+    on_change: Option<String>,
+    on_delete: Option<String>,
+    on_booked: Option<String>,
+    /// Runs before the schedule is saved, a non-zero exit aborts the change.
+    pre_save: Option<String>,
+    post_ics: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+struct FeedConfig {
+    /// Path of the ICS file of the feed.
+    file: PathBuf,
+    /// X-WR-CALNAME of the feed, defaults to the name of the feed.
+    calendar_name: Option<String>,
+    past_days: Option<i64>,
+    future_days: Option<i64>,
+    export_notes: Option<bool>,
+    summary_depth: Option<usize>,
+    /// Only export events of these projects or project:task paths.
+    #[serde(default)]
+    projects: Vec<String>,
+    /// Only export events with one of these tags.
+    #[serde(default)]
+    tags: Vec<String>,
+    /// Export rules of the feed, replacing the global export_rules.
+    export_rules: Option<BTreeMap<String, ExportRule>>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Default)]
+struct ExportRule {
+    /// Export matching events at all, defaults to true.
+    export: Option<bool>,
+    /// Summary template with {project}, {task} and {path}, e.g. "{project}: {task}" or "Busy".
+    summary: Option<String>,
+    /// Export the location, defaults to true.
+    location: Option<bool>,
+    /// Export the note, defaults to export_notes.
+    notes: Option<bool>,
+    /// Categories exported instead of the tags of the event.
+    categories: Option<Vec<String>>,
+    /// CSS color name exported as COLOR.
+    color: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+struct FreeBusyConfig {
+    /// Path of the free/busy ICS file.
+    file: PathBuf,
+    /// Days in the past to export, defaults to 7.
+    past_days: Option<i64>,
+    /// Days in the future to export, defaults to 90.
+    future_days: Option<i64>,
+    /// Export plain "Busy" events instead of a VFREEBUSY component.
+    #[serde(default)]
+    events: bool,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+struct ServeConfig {
+    /// Credentials for basic auth, the server is open if not set.
+    username: Option<String>,
+    password: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+struct CaldavConfig {
+    /// URL of the calendar collection.
+    url: String,
+    username: Option<String>,
+    password: Option<String>,
+    /// Command printing the password, used if no password is set.
+    password_command: Option<String>,
+    /// Days in the past to sync, defaults to 7 like the ICS export.
+    past_days: Option<i64>,
+    /// Apply the export rules to the uploaded events, defaults to true.
+    export_rules: Option<bool>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum BillingAggregate {
+    Event,
+    Day,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum BillingRounding {
+    Up,
+    Nearest,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+struct BillingPolicy {
+    /// Billing increment in minutes.
+    increment: u32,
+    /// Round every single event or the sum per day and task.
+    aggregate: BillingAggregate,
+    rounding: BillingRounding,
+}
+
+impl BillingPolicy {
+    fn bill(&self, duration: Duration) -> Duration {
+        let increment = self.increment.max(1) as i64;
+        let minutes = duration.num_minutes();
+        let units = match self.rounding {
+            BillingRounding::Up => (minutes + increment - 1) / increment,
+            BillingRounding::Nearest => (minutes + increment / 2) / increment,
+        };
+        Duration::minutes(units * increment)
+    }
+
+    // Billed duration of the booked events of a task, grouped per event or per day
+    fn bill_events(&self, events: &[&ScheduleEvent], timezone: &Tz) -> Duration {
+        let booked = events.iter().filter(|event| event.booked);
+        match self.aggregate {
+            BillingAggregate::Event => booked.map(|event| self.bill(event.end_time - event.start_time)).sum(),
+            BillingAggregate::Day => {
+                let mut per_day: HashMap<NaiveDate, Duration> = HashMap::new();
+                for event in booked {
+                    *per_day.entry(event.start_time.with_timezone(timezone).date_naive()).or_insert(Duration::zero()) += event.end_time - event.start_time;
+                }
+                per_day.into_values().map(|duration| self.bill(duration)).sum()
+            }
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+struct LocationInfo {
+    address: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+struct LocationDistance {
+    from: String,
+    to: String,
+    km: f64,
+}
+
+impl Config {
+    fn distance(&self, from: &str, to: &str) -> Option<f64> {
+        self.distances
+            .iter()
+            .find(|distance| (distance.from == from && distance.to == to) || (distance.from == to && distance.to == from))
+            .map(|distance| distance.km)
+    }
+
+    fn ics_options(&self) -> IcsOptions<'_> {
+        IcsOptions {
+            calendar_name: self.ics_calendar_name.as_deref().unwrap_or(APP_NAME),
+            timezone: self.ics_timezone.as_deref().and_then(|timezone| timezone.parse().ok()),
+            export_notes: self.export_notes.unwrap_or(true),
+            summary_depth: self.ics_summary_depth.unwrap_or(1),
+            rules: &self.export_rules,
+            past_days: self.ics_past_days.unwrap_or(7),
+            future_days: self.ics_future_days,
+            projects: &[],
+            tags: &[],
+        }
+    }
+
+    // Options of a feed, falling back to the options of the main ICS file
+    fn feed_options<'a>(&'a self, name: &'a str, feed: &'a FeedConfig) -> IcsOptions<'a> {
+        let defaults = self.ics_options();
+        IcsOptions {
+            calendar_name: feed.calendar_name.as_deref().unwrap_or(name),
+            timezone: defaults.timezone,
+            export_notes: feed.export_notes.unwrap_or(defaults.export_notes),
+            summary_depth: feed.summary_depth.unwrap_or(defaults.summary_depth),
+            rules: feed.export_rules.as_ref().unwrap_or(defaults.rules),
+            past_days: feed.past_days.unwrap_or(defaults.past_days),
+            future_days: feed.future_days.or(defaults.future_days),
+            projects: &feed.projects,
+            tags: &feed.tags,
+        }
+    }
+
+    fn state_file(&self) -> PathBuf {
+        self.state_file.clone().unwrap_or_else(|| self.schedule_file.with_file_name("state.json"))
+    }
+
+    fn address<'a>(&'a self, location: &'a str) -> &'a str {
+        self.locations.get(location).and_then(|info| info.address.as_deref()).unwrap_or(location)
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Default)]
+struct WeeklyHours {
+    mon: Option<f64>,
+    tue: Option<f64>,
+    wed: Option<f64>,
+    thu: Option<f64>,
+    fri: Option<f64>,
+    sat: Option<f64>,
+    sun: Option<f64>,
+}
+
+impl WeeklyHours {
+    fn target(&self, weekday: Weekday) -> Duration {
+        let hours = match weekday {
+            Weekday::Mon => self.mon,
+            Weekday::Tue => self.tue,
+            Weekday::Wed => self.wed,
+            Weekday::Thu => self.thu,
+            Weekday::Fri => self.fri,
+            Weekday::Sat => self.sat,
+            Weekday::Sun => self.sun,
+        };
+        Duration::minutes((hours.unwrap_or(0.0) * 60.0) as i64)
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+struct WorkingTime {
+    /// First day of the working time account.
+    start_date: NaiveDate,
+    /// Contractual hours per weekday.
+    #[serde(default)]
+    hours: WeeklyHours,
+    /// Public holidays, as YYYY-MM-DD.
+    #[serde(default)]
+    holidays: Vec<NaiveDate>,
+    /// Absences (vacation, sick leave), as YYYY-MM-DD or YYYY-MM-DD..YYYY-MM-DD.
+    #[serde(default)]
+    absences: Vec<String>,
+}
+
+impl WorkingTime {
+    fn absence_ranges(&self) -> Result<Vec<(NaiveDate, NaiveDate)>, Error> {
+        self.absences.iter().map(|absence| {
+            let (from, to) = absence.split_once("..").unwrap_or((absence, absence));
+            let from = NaiveDate::parse_from_str(from.trim(), "%Y-%m-%d");
+            let to = NaiveDate::parse_from_str(to.trim(), "%Y-%m-%d");
+            match (from, to) {
+                (Ok(from), Ok(to)) => Ok((from, to)),
+                _ => Err(Error::new(ErrorKind::InvalidData, format!("Invalid absence in config file: {}", absence))),
+            }
+        }).collect()
+    }
 }
 
 impl Config {
@@ -276,13 +931,33 @@ impl Config {
                 rounding: Some(15),
                 timezone: None,
                 push_command: None,
+                push_timeout: None,
+                auto_push: false,
+                ics_summary_depth: None,
+                home_locations: Vec::new(),
+                locations: BTreeMap::new(),
+                distances: Vec::new(),
+                working_time: None,
+                billing: HashMap::new(),
+                state_file: None,
+                caldav: None,
+                serve: None,
+                freebusy: None,
+                export_rules: BTreeMap::new(),
+                ics_past_days: None,
+                ics_future_days: None,
+                ics_calendar_name: None,
+                ics_timezone: None,
+                overlays: BTreeMap::new(),
+                feeds: BTreeMap::new(),
+                hooks: None,
             };
 
             std::fs::create_dir_all(config_parent)?; // Ensure config directory exists
             let config_str = to_string_pretty(&default_config).map_err(|e| {
-                Error::new(ErrorKind::Other, format!("Failed to serialize default config: {}", e))
+                Error::other(format!("Failed to serialize default config: {}", e))
             })?;
-            std::fs::write(&config_file, config_str)?;
+            std::fs::write(config_file, config_str)?;
 
 
             println!("Created default config file at: {}", config_file.display());
@@ -290,7 +965,7 @@ impl Config {
         }
 
         // Load config if it exists
-        let config_str = std::fs::read_to_string(&config_file)?;
+        let config_str = std::fs::read_to_string(config_file)?;
         let config = from_str(&config_str).map_err(|e| {
             Error::new(ErrorKind::InvalidData, format!("Invalid config file: {}", e))
         })?;
@@ -352,7 +1027,7 @@ fn parse_datetime_range(timespan: &str, date_str: Option<&str>, interval: u32, t
 
     // Handle overnight events
     if end_datetime_local < start_datetime_local {
-        end_datetime_local = end_datetime_local + Duration::days(1);
+        end_datetime_local += Duration::days(1);
     }
 
 
@@ -367,11 +1042,11 @@ fn parse_datetime_range(timespan: &str, date_str: Option<&str>, interval: u32, t
 
 fn merge_events(events: &mut Vec<ScheduleEvent>) {
     // Sort events by all relevant fields for grouping
-    events.sort_by_key(|event| (event.summary.clone(), event.note.clone(), event.location.clone(), event.booked, event.start_time));
+    events.sort_by_key(|event| (event.summary.clone(), event.note.clone(), event.location.clone(), event.booked, event.tags.clone(), event.planned_ahead, event.start_time));
 
     let merged_events: Vec<ScheduleEvent> = events
         .iter()
-        .chunk_by(|event| (event.summary.clone(), event.note.clone(), event.location.clone(), event.booked))
+        .chunk_by(|event| (event.summary.clone(), event.note.clone(), event.location.clone(), event.booked, event.tags.clone(), event.planned_ahead))
         .into_iter()
         .flat_map(|(_, group)| {
             let mut merged_events: Vec<ScheduleEvent> = Vec::new();
@@ -398,25 +1073,96 @@ fn merge_events(events: &mut Vec<ScheduleEvent>) {
 }
 
 fn split_overlapping_events(events: &mut Vec<ScheduleEvent>, new_event: ScheduleEvent, timezone: &Tz) -> bool {
+    let original_events = events.clone();
+    let overlaps_exist = insert_event(events, new_event);
+    if overlaps_exist {
+        print_event_diff(&original_events, events, timezone);
+    }
+    overlaps_exist
+}
+
+// Adds the event, existing events are cut where they overlap with it
+fn insert_event(events: &mut Vec<ScheduleEvent>, new_event: ScheduleEvent) -> bool {
+    let mut overlaps_exist = false;
+    for part in planned_parts(events, new_event) {
+        overlaps_exist |= cut_in_event(events, part);
+    }
+    overlaps_exist
+}
+
+// Booking over a plan of the same task still counts as planned, a booking reaching beyond the plan is split there
+fn planned_parts(events: &[ScheduleEvent], new_event: ScheduleEvent) -> Vec<ScheduleEvent> {
+    if !new_event.booked || new_event.planned_ahead != Some(false) {
+        return vec![new_event];
+    }
+    let plans: Vec<ScheduleEvent> = events.iter().filter(|event| event.summary == new_event.summary).cloned().collect();
+    let mut parts = Vec::new();
+    let mut start = new_event.start_time;
+    for (plan_start, plan_end) in busy_intervals(&plans, false, new_event.start_time, new_event.end_time) {
+        if start < plan_start {
+            parts.push((start, plan_start, false));
+        }
+        parts.push((plan_start, plan_end, true));
+        start = plan_end;
+    }
+    if start < new_event.end_time {
+        parts.push((start, new_event.end_time, false));
+    }
+    parts
+        .into_iter()
+        .enumerate()
+        .map(|(index, (start_time, end_time, planned))| ScheduleEvent {
+            id: if index == 0 { new_event.id.clone() } else { Uuid::new_v4().to_string() },
+            start_time,
+            end_time,
+            planned_ahead: Some(planned),
+            ..new_event.clone()
+        })
+        .collect()
+}
+
+// Busy intervals within the window, overlapping and adjacent intervals merged
+fn busy_intervals(events: &[ScheduleEvent], booked: bool, from: DateTime<Utc>, to: DateTime<Utc>) -> Vec<(DateTime<Utc>, DateTime<Utc>)> {
+    let mut intervals: Vec<(DateTime<Utc>, DateTime<Utc>)> = Vec::new();
+    for event in events.iter().filter(|event| event.booked == booked && event.end_time > from && event.start_time < to).sorted_by_key(|event| event.start_time) {
+        let (start, end) = (event.start_time.max(from), event.end_time.min(to));
+        match intervals.last_mut() {
+            Some(last) if start <= last.1 => last.1 = last.1.max(end),
+            _ => intervals.push((start, end)),
+        }
+    }
+    intervals
+}
+
+fn cut_in_event(events: &mut Vec<ScheduleEvent>, mut new_event: ScheduleEvent) -> bool {
+    // An event of the same task which is replaced completely passes on its UID, e.g. when booking exactly over a plan
+    if let Some(covered) = events.iter().find(|event| event.summary == new_event.summary && new_event.start_time <= event.start_time && event.end_time <= new_event.end_time) {
+        new_event.id = covered.id.clone();
+    }
+
     let mut overlaps_exist = false;
     let mut new_events = Vec::new();
-    let original_events = events.clone();
 
     for existing_event in events.drain(..) {
         if new_event.start_time < existing_event.end_time && new_event.end_time > existing_event.start_time {
             // Overlap: Split existing event
             overlaps_exist = true;
 
-            if new_event.start_time > existing_event.start_time {
+            // The first remaining portion keeps the UID, so calendar clients see an update
+            let keeps_before = new_event.start_time > existing_event.start_time;
+            if keeps_before {
                 // Add the portion of the existing event before the new event
                 let before_event = ScheduleEvent {
-                    id: Uuid::new_v4().to_string(),
+                    id: existing_event.id.clone(),
                     start_time: existing_event.start_time,
                     end_time: new_event.start_time,
                     summary: existing_event.summary.clone(),
                     note: existing_event.note.clone(),
                     location: existing_event.location.clone(),
                     booked: existing_event.booked,
+                    tags: existing_event.tags.clone(),
+                    planned_ahead: existing_event.planned_ahead,
+                    overlay: None,
                 };
                 new_events.push(before_event);
 
@@ -425,13 +1171,16 @@ fn split_overlapping_events(events: &mut Vec<ScheduleEvent>, new_event: Schedule
             if new_event.end_time < existing_event.end_time {
                 // Add portion of the existing event after the new event
                 let after_event = ScheduleEvent {
-                    id: Uuid::new_v4().to_string(),
+                    id: if keeps_before { Uuid::new_v4().to_string() } else { existing_event.id.clone() },
                     start_time: new_event.end_time,
                     end_time: existing_event.end_time,
                     summary: existing_event.summary.clone(),
                     note: existing_event.note.clone(),
                     location: existing_event.location.clone(),
                     booked: existing_event.booked,
+                    tags: existing_event.tags.clone(),
+                    planned_ahead: existing_event.planned_ahead,
+                    overlay: None,
                 };
 
                 new_events.push(after_event);
@@ -449,12 +1198,8 @@ fn split_overlapping_events(events: &mut Vec<ScheduleEvent>, new_event: Schedule
     // Replace original events with modified ones
     *events = new_events;
     // Sort events by start time
-    events.sort_by(|a, b| a.start_time.cmp(&b.start_time));
+    events.sort_by_key(|a| a.start_time);
     merge_events(events); // Merge after splitting and adding
-
-    if overlaps_exist {
-        print_event_diff(&original_events, events, &timezone);
-    }
     overlaps_exist
 }
 
@@ -475,7 +1220,7 @@ fn print_event_diff(before: &[ScheduleEvent], after: &[ScheduleEvent], timezone:
     for id in after_map.keys() {
         if !before_map.contains_key(id) {
             println!("+ {}", format_event_for_diff(after_map[id], timezone).green());
-        } else if before_map[id] != after_map[id] { // Direct comparison
+        } else if event_hash(before_map[id]) != event_hash(after_map[id]) { // Events are equal by ID, compare the content
             let before_event = before_map[id];
             let after_event = after_map[id];
             println!("~ {}", format_event_change_for_diff(before_event, after_event, timezone).yellow());
@@ -488,17 +1233,21 @@ fn format_event_for_diff(event: &ScheduleEvent, timezone: &Tz) -> String {
     let start_time = event.start_time.with_timezone(timezone);
     let end_time = event.end_time.with_timezone(timezone);
     format!(
-        "{} - {} {} ({}) {} {}",
+        "{} - {} {} ({}) {} {} {}",
         start_time.format("%Y-%m-%d %H:%M"),
         end_time.format("%H:%M"),
         event.summary,
         event.id,
         event.note.as_deref().unwrap_or_default(),
-        event.location.as_deref().unwrap_or_default()
-
+        event.location.as_deref().unwrap_or_default(),
+        format_tags(&event.tags)
     )
 }
 
+fn format_tags(tags: &[String]) -> String {
+    tags.iter().map(|tag| format!("#{}", tag)).join(" ")
+}
+
 fn format_event_change_for_diff(before: &ScheduleEvent, after: &ScheduleEvent, timezone: &Tz) -> String {
     let mut changes = Vec::new();
 
@@ -547,6 +1296,9 @@ fn format_event_change_for_diff(before: &ScheduleEvent, after: &ScheduleEvent, t
     if before.booked != after.booked {
         changes.push(format!("~  booked  : {} → {}", before.booked, after.booked).yellow().to_string());
     }
+    if before.tags != after.tags {
+        changes.push(format!("~  tags    : {} → {}", format_tags(&before.tags), format_tags(&after.tags)).yellow().to_string());
+    }
 
     format!(
         "{} ({})\n{}",
@@ -556,433 +1308,3509 @@ fn format_event_change_for_diff(before: &ScheduleEvent, after: &ScheduleEvent, t
     )
 }
 
-fn load_events(file_path: &PathBuf) -> Result<Vec<ScheduleEvent>, Error> {
-    match File::open(file_path) {
-        Ok(file) => serde_json::from_reader(file).map_err(|e| {
-            Error::new(
-                ErrorKind::InvalidData,
-                format!("Failed to parse schedule file: {}", e),
-            )
-        }),
-        Err(e) if e.kind() == ErrorKind::NotFound => Ok(Vec::new()),
-        Err(e) => Err(e),
-    }
+// Exit codes besides 0 (success), 1 (error) and 2 (invalid arguments)
+const EXIT_NEGATIVE: u8 = 3; // The slot is not free or there is no current event
+const EXIT_NOT_APPLIED: u8 = 4; // The change was declined or there was nothing to change
+
+// Event as written by --output json and tsv, kept stable for scripts
+#[derive(Serialize)]
+struct EventOutput<'a> {
+    id: &'a str,
+    start: String,
+    end: String,
+    minutes: i64,
+    summary: &'a str,
+    project: &'a str,
+    task: &'a str,
+    status: &'static str,
+    location: Option<&'a str>,
+    note: Option<&'a str>,
+    tags: &'a [String],
+    overlay: Option<&'a str>,
 }
 
-fn save_events(file_path: &PathBuf, events: &[ScheduleEvent]) -> Result<(), Error> {
-    let mut file = OpenOptions::new()
-        .write(true)
-        .create(true)
-        .truncate(true)
-        .open(file_path)?;
-
-    file.seek(SeekFrom::Start(0))?;
-    file.set_len(0)?;
+impl<'a> EventOutput<'a> {
+    fn new(event: &'a ScheduleEvent, timezone: &Tz) -> Self {
+        let (project, task) = event.summary.split_once(':').unwrap_or((&event.summary, ""));
+        let status = if event.overlay.is_some() {
+            "overlay"
+        } else if event.booked {
+            "booked"
+        } else if event.end_time < Utc::now() {
+            "missed"
+        } else {
+            "planned"
+        };
+        EventOutput {
+            id: &event.id,
+            start: event.start_time.with_timezone(timezone).to_rfc3339(),
+            end: event.end_time.with_timezone(timezone).to_rfc3339(),
+            minutes: (event.end_time - event.start_time).num_minutes(),
+            summary: &event.summary,
+            project,
+            task,
+            status,
+            location: event.location.as_deref(),
+            note: event.note.as_deref(),
+            tags: &event.tags,
+            overlay: event.overlay.as_deref(),
+        }
+    }
 
-    serde_json::to_writer(&file, events)?;
-    Ok(())
+    fn tsv_row(&self) -> String {
+        [
+            self.id,
+            &self.start,
+            &self.end,
+            &self.minutes.to_string(),
+            self.summary,
+            self.status,
+            self.location.unwrap_or_default(),
+            &self.tags.join(","),
+            self.note.unwrap_or_default(),
+            self.overlay.unwrap_or_default(),
+        ]
+        .iter()
+        .map(|field| tsv_field(field))
+        .join("\t")
+    }
 }
 
-fn generate_ics(file_path: &PathBuf, events: &[ScheduleEvent], export_notes: bool) -> Result<(), Error> {
-    let mut calendar = ICalendar::new("2.0", "-//plantrack//plantrack version 1.0//EN");
+const EVENT_TSV_HEADER: &str = "id\tstart\tend\tminutes\tsummary\tstatus\tlocation\ttags\tnote\toverlay";
 
-    let now = Utc::now().with_second(0).unwrap().with_nanosecond(0).unwrap();
-    let past_cutoff = now - Duration::days(7); // Include past 7 days in the export
+fn tsv_field(value: &str) -> String {
+    value.replace(['\t', '\n', '\r'], " ")
+}
 
-    let mut exported_events_count = 0;
+fn print_json<T: Serialize>(value: &T) -> Result<(), Error> {
+    writeln!(std::io::stdout(), "{}", serde_json::to_string_pretty(value)?)
+}
 
+fn print_events_tsv(events: &[&ScheduleEvent], timezone: &Tz) {
+    println!("{}", EVENT_TSV_HEADER);
     for event in events {
-        // Export future events and events within the past time window
-        if event.start_time >= past_cutoff {
-            let mut ics_event = Event::new(event.id.clone(), event.start_time.format("%Y%m%dT%H%M%SZ").to_string());
-            let (project, _) = event.summary.split_once(':').ok_or_else(|| Error::new(ErrorKind::InvalidInput, "Invalid project:task format"))?;
-            ics_event.push(Summary::new(project.trim()));
-            ics_event.push(DtStart::new(event.start_time.format("%Y%m%dT%H%M%SZ").to_string()));
-            ics_event.push(DtEnd::new(event.end_time.format("%Y%m%dT%H%M%SZ").to_string()));
-
-            ics_event.push(if event.booked { Status::new("CONFIRMED") } else { Status::new("TENTATIVE") });
-
-            if export_notes {
-                if let Some(note) = &event.note {
-                    ics_event.push(Description::new(note.clone()));
-                }
-            }
-            if let Some(loc) = &event.location {
-                ics_event.push(Location::new(loc.clone()));
-            }
-
-            calendar.add_event(ics_event);
-            exported_events_count += 1;
-        }
+        println!("{}", EventOutput::new(event, timezone).tsv_row());
     }
+}
 
+#[derive(Serialize)]
+struct CurrentOutput<'a> {
+    event: Option<EventOutput<'a>>,
+    unpublished: bool,
+}
+
+#[derive(Serialize)]
+struct ChangeOutput<'a> {
+    before: EventOutput<'a>,
+    after: EventOutput<'a>,
+}
+
+#[derive(Serialize)]
+struct ChangesOutput<'a> {
+    applied: bool,
+    added: Vec<EventOutput<'a>>,
+    removed: Vec<EventOutput<'a>>,
+    changed: Vec<ChangeOutput<'a>>,
+}
+
+fn event_changes<'a>(before: &'a [ScheduleEvent], after: &'a [ScheduleEvent], applied: bool, timezone: &Tz) -> ChangesOutput<'a> {
+    let before_map: BTreeMap<&str, &ScheduleEvent> = before.iter().map(|e| (e.id.as_str(), e)).collect();
+    let after_map: BTreeMap<&str, &ScheduleEvent> = after.iter().map(|e| (e.id.as_str(), e)).collect();
+    ChangesOutput {
+        applied,
+        added: after.iter().filter(|e| !before_map.contains_key(e.id.as_str())).map(|e| EventOutput::new(e, timezone)).collect(),
+        removed: before.iter().filter(|e| !after_map.contains_key(e.id.as_str())).map(|e| EventOutput::new(e, timezone)).collect(),
+        changed: after
+            .iter()
+            .filter_map(|e| before_map.get(e.id.as_str()).filter(|before_event| event_hash(before_event) != event_hash(e)).map(|before_event| (*before_event, e)))
+            .map(|(before_event, after_event)| ChangeOutput { before: EventOutput::new(before_event, timezone), after: EventOutput::new(after_event, timezone) })
+            .collect(),
+    }
+}
+
+// Writes the changes of add, set and delete for json and tsv output, text output shows them before the confirmation
+fn print_changes(before: &[ScheduleEvent], after: &[ScheduleEvent], applied: bool, output: OutputFormat, timezone: &Tz) -> Result<(), Error> {
+    let changes = event_changes(before, after, applied, timezone);
+    match output {
+        OutputFormat::Text => {}
+        OutputFormat::Json => print_json(&changes)?,
+        OutputFormat::Tsv => {
+            println!("change\t{}", EVENT_TSV_HEADER);
+            let rows = changes.added.iter().map(|event| ("added", event))
+                .chain(changes.removed.iter().map(|event| ("removed", event)))
+                .chain(changes.changed.iter().map(|change| ("changed", &change.after)));
+            for (change, event) in rows {
+                println!("{}\t{}", change, event.tsv_row());
+            }
+        }
+    }
+    Ok(())
+}
+
+// Payload of a hook on stdin, the changes of the schedule plus the events which became booked
+#[derive(Serialize)]
+struct HookPayload<'a> {
+    hook: &'static str,
+    #[serde(flatten)]
+    changes: &'a ChangesOutput<'a>,
+    booked: Vec<BookedOutput<'a>>,
+}
+
+// An event with newly booked time, booking next to a booked event of the same task extends it
+#[derive(Clone, Serialize)]
+struct BookedOutput<'a> {
+    #[serde(flatten)]
+    event: &'a EventOutput<'a>,
+    booked_minutes: i64,
+}
+
+#[derive(Serialize)]
+struct PostIcsPayload<'a> {
+    hook: &'static str,
+    files: Vec<&'a PathBuf>,
+}
+
+// Runs a hook command with the payload as JSON on stdin, its output goes to stderr to keep --output json clean
+fn run_hook(config: &Config, hook: &str, command: &str, payload: &impl Serialize) -> Result<(), Error> {
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .env("PLANTRACK_HOOK", hook)
+        .env("PLANTRACK_SCHEDULE_FILE", &config.schedule_file)
+        .env("PLANTRACK_ICS_FILE", &config.ics_file)
+        .stdin(Stdio::piped())
+        .stdout(std::io::stderr())
+        .spawn()?;
+    if let Some(mut stdin) = child.stdin.take() {
+        // Hooks are free to ignore their input
+        match serde_json::to_writer(&mut stdin, payload) {
+            Err(e) if e.io_error_kind() == Some(ErrorKind::BrokenPipe) => {}
+            result => result?,
+        }
+    }
+    let status = child.wait()?;
+    if !status.success() {
+        return Err(Error::other(format!("{} hook failed with exit code: {}", hook, status)));
+    }
+    Ok(())
+}
+
+// Added booked events, events changed to booked and booked events which grew
+fn newly_booked<'a>(changes: &'a ChangesOutput<'a>) -> Vec<BookedOutput<'a>> {
+    let added = changes.added.iter().filter(|event| event.status == "booked").map(|event| BookedOutput { event, booked_minutes: event.minutes });
+    let changed = changes.changed.iter().filter(|change| change.after.status == "booked").filter_map(|change| {
+        let booked_minutes = match change.before.status {
+            "booked" => change.after.minutes - change.before.minutes,
+            _ => change.after.minutes,
+        };
+        (booked_minutes > 0).then_some(BookedOutput { event: &change.after, booked_minutes })
+    });
+    added.chain(changed).collect()
+}
+
+// Saves the schedule, pre_save can abort the change, the other hooks are told about it afterwards
+fn save_schedule(config: &Config, before: &[ScheduleEvent], after: &[ScheduleEvent], timezone: &Tz) -> Result<(), Error> {
+    run_pre_save(config, before, after, timezone)?;
+    write_schedule(config, before, after, timezone)
+}
+
+// Runs the pre_save hook, an error aborts the change
+fn run_pre_save(config: &Config, before: &[ScheduleEvent], after: &[ScheduleEvent], timezone: &Tz) -> Result<(), Error> {
+    let Some(command) = config.hooks.as_ref().and_then(|hooks| hooks.pre_save.as_ref()) else {
+        return Ok(());
+    };
+    let changes = event_changes(before, after, false, timezone);
+    if changes.added.is_empty() && changes.removed.is_empty() && changes.changed.is_empty() {
+        return Ok(());
+    }
+    run_hook(config, "pre_save", command, &HookPayload { hook: "pre_save", changes: &changes, booked: newly_booked(&changes) })
+}
+
+// Saves the schedule without asking pre_save again and runs the hooks after saving
+fn write_schedule(config: &Config, before: &[ScheduleEvent], after: &[ScheduleEvent], timezone: &Tz) -> Result<(), Error> {
+    save_events(&config.schedule_file, after)?;
+    let Some(hooks) = &config.hooks else {
+        return Ok(());
+    };
+    let changes = event_changes(before, after, true, timezone);
+    let post_hooks = [
+        ("on_add", &hooks.on_add, !changes.added.is_empty()),
+        ("on_change", &hooks.on_change, !changes.changed.is_empty()),
+        ("on_delete", &hooks.on_delete, !changes.removed.is_empty()),
+    ];
+    let booked = newly_booked(&changes);
+    for (hook, command, triggered) in post_hooks.into_iter().chain([("on_booked", &hooks.on_booked, !booked.is_empty())]) {
+        if let (Some(command), true) = (command, triggered) {
+            // The change is saved already, a failing hook is only reported
+            if let Err(e) = run_hook(config, hook, command, &HookPayload { hook, changes: &changes, booked: booked.clone() }) {
+                eprintln!("{}", e.to_string().red());
+            }
+        }
+    }
+    Ok(())
+}
+
+// Adds a new event, text output shows the changes to existing events and overlay conflicts
+fn add_event(events: &mut Vec<ScheduleEvent>, overlay_events: &[ScheduleEvent], event: ScheduleEvent, output: OutputFormat, timezone: &Tz) -> bool {
+    if output != OutputFormat::Text {
+        let overlay_conflict = is_slot_free(overlay_events, event.start_time, event.end_time).is_err();
+        return insert_event(events, event) || overlay_conflict;
+    }
+    let overlaps = split_overlapping_events(events, event.clone(), timezone);
+    let overlaps = print_overlay_conflicts(overlay_events, &event, timezone) || overlaps;
+    if !overlaps {
+        println!("{}", "New event:".yellow().bold());
+        println!("+ {}", format_event_for_diff(&event, timezone).green());
+    }
+    overlaps
+}
+
+fn load_events(file_path: &PathBuf) -> Result<Vec<ScheduleEvent>, Error> {
+    match File::open(file_path) {
+        Ok(file) => serde_json::from_reader(file).map_err(|e| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!("Failed to parse schedule file: {}", e),
+            )
+        }),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e),
+    }
+}
+
+fn save_events(file_path: &PathBuf, events: &[ScheduleEvent]) -> Result<(), Error> {
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(file_path)?;
+
+    file.seek(SeekFrom::Start(0))?;
+    file.set_len(0)?;
+
+    serde_json::to_writer(&file, events)?;
+    Ok(())
+}
+
+struct IcsOptions<'a> {
+    calendar_name: &'a str,
+    timezone: Option<Tz>,
+    export_notes: bool,
+    summary_depth: usize,
+    rules: &'a BTreeMap<String, ExportRule>,
+    past_days: i64,
+    future_days: Option<i64>,
+    projects: &'a [String],
+    tags: &'a [String],
+}
+
+// The most specific rule matching the project path, "*" matches all events
+fn export_rule<'a>(rules: &'a BTreeMap<String, ExportRule>, summary: &str) -> Option<&'a ExportRule> {
+    rules
+        .iter()
+        .filter(|(pattern, _)| matches_path(summary, pattern))
+        .max_by_key(|(pattern, _)| pattern.len())
+        .map(|(_, rule)| rule)
+        .or_else(|| rules.get("*"))
+}
+
+fn render_summary(template: &str, summary: &str) -> String {
+    let (project, task) = summary.split_once(':').unwrap_or((summary, ""));
+    template.replace("{project}", project).replace("{task}", task).replace("{path}", summary)
+}
+
+// UTC, or local time if the export has a timezone
+fn ics_time(time: DateTime<Utc>, timezone: Option<Tz>) -> String {
+    match timezone {
+        Some(timezone) => time.with_timezone(&timezone).format("%Y%m%dT%H%M%S").to_string(),
+        None => time.format("%Y%m%dT%H%M%SZ").to_string(),
+    }
+}
+
+fn ics_event(event: &ScheduleEvent, options: &IcsOptions, rule: Option<&ExportRule>, revision: Option<&IcsRevision>) -> Event<'static> {
+    // DTSTAMP is the time of the last change, as the calendar has no METHOD
+    let last_modified = revision.map_or_else(Utc::now, |revision| revision.last_modified).format("%Y%m%dT%H%M%SZ").to_string();
+    let mut ics_event = Event::new(event.id.clone(), last_modified.clone());
+    ics_event.push(LastModified::new(last_modified));
+    ics_event.push(Sequence::new(revision.map_or(0, |revision| revision.sequence).to_string()));
+    ics_event.push(Summary::new(export_summary(event, options, rule)));
+    let mut start = DtStart::new(ics_time(event.start_time, options.timezone));
+    let mut end = DtEnd::new(ics_time(event.end_time, options.timezone));
+    if let Some(timezone) = options.timezone {
+        start.add(TzIDParam::new(timezone.name()));
+        end.add(TzIDParam::new(timezone.name()));
+    }
+    ics_event.push(start);
+    ics_event.push(end);
+
+    ics_event.push(if event.booked { Status::new("CONFIRMED") } else { Status::new("TENTATIVE") });
+
+    if rule.and_then(|rule| rule.notes).unwrap_or(options.export_notes) {
+        if let Some(note) = &event.note {
+            ics_event.push(Description::new(note.clone()));
+        }
+    }
+    if rule.and_then(|rule| rule.location).unwrap_or(true) {
+        if let Some(loc) = &event.location {
+            ics_event.push(Location::new(loc.clone()));
+        }
+    }
+    let categories = rule.and_then(|rule| rule.categories.as_ref()).unwrap_or(&event.tags);
+    if !categories.is_empty() {
+        ics_event.push(Categories::new(categories.join(",")));
+    }
+    if let Some(color) = rule.and_then(|rule| rule.color.clone()) {
+        ics_event.push(Property::new("COLOR", color));
+    }
+    ics_event
+}
+
+fn generate_ics(file_path: &PathBuf, events: &[ScheduleEvent], options: &IcsOptions, revisions: &BTreeMap<String, IcsRevision>) -> Result<(), Error> {
+    let (calendar, exported_events_count) = ics_calendar(events, options, revisions);
     calendar.save_file(file_path)?;
-    println!("{} events exported to {}", exported_events_count, file_path.display());
+    eprintln!("{} events exported to {}", exported_events_count, file_path.display());
+    Ok(())
+}
+
+fn export_summary(event: &ScheduleEvent, options: &IcsOptions, rule: Option<&ExportRule>) -> String {
+    match rule.and_then(|rule| rule.summary.as_deref()) {
+        Some(template) => render_summary(template, &event.summary),
+        None => event.summary.split(':').take(options.summary_depth.max(1)).map(|segment| segment.trim()).join(":"),
+    }
+}
+
+// The event as shown in the exported calendar, without what the export rules hide
+fn shared_event(event: &ScheduleEvent, options: &IcsOptions, rule: Option<&ExportRule>) -> ScheduleEvent {
+    ScheduleEvent {
+        summary: export_summary(event, options, rule),
+        note: event.note.clone().filter(|_| rule.and_then(|rule| rule.notes).unwrap_or(options.export_notes)),
+        location: event.location.clone().filter(|_| rule.and_then(|rule| rule.location).unwrap_or(true)),
+        tags: rule.and_then(|rule| rule.categories.clone()).unwrap_or_else(|| event.tags.clone()),
+        planned_ahead: None,
+        ..event.clone()
+    }
+}
+
+// Events within the time window, matching the project and tag filters and the export rules of the feed
+fn exported_events<'a>(events: &'a [ScheduleEvent], options: &IcsOptions<'a>) -> Vec<(&'a ScheduleEvent, Option<&'a ExportRule>)> {
+    let now = Utc::now().with_second(0).unwrap().with_nanosecond(0).unwrap();
+    let past_cutoff = now - Duration::days(options.past_days);
+    let future_cutoff = options.future_days.map(|days| now + Duration::days(days));
+
+    events
+        .iter()
+        .filter_map(|event| {
+            let in_window = event.start_time >= past_cutoff && future_cutoff.is_none_or(|cutoff| event.start_time <= cutoff);
+            let in_projects = options.projects.is_empty() || options.projects.iter().any(|project| matches_path(&event.summary, project));
+            let in_tags = options.tags.is_empty() || options.tags.iter().any(|tag| event.has_tag(tag));
+            let rule = export_rule(options.rules, &event.summary);
+            (in_window && in_projects && in_tags && rule.and_then(|rule| rule.export).unwrap_or(true)).then_some((event, rule))
+        })
+        .collect()
+}
+
+// Writes the ICS file, all configured feeds and the free/busy file
+fn export_ics(config: &Config, events: &[ScheduleEvent]) -> Result<(), Error> {
+    let state_file_path = config.state_file();
+    let mut state = load_state(&state_file_path)?;
+    if update_revisions(&mut state.ics, events) {
+        save_state(&state_file_path, &state)?;
+    }
+
+    generate_ics(&config.ics_file, events, &config.ics_options(), &state.ics)?;
+    for (name, feed) in &config.feeds {
+        generate_ics(&feed.file, events, &config.feed_options(name, feed), &state.ics)?;
+    }
+    if let Some(freebusy) = &config.freebusy {
+        generate_freebusy(freebusy, events)?;
+    }
+    if let Some(command) = config.hooks.as_ref().and_then(|hooks| hooks.post_ics.as_ref()) {
+        let files = ics_files(config).into_iter().chain(config.freebusy.as_ref().map(|freebusy| &freebusy.file)).collect();
+        if let Err(e) = run_hook(config, "post_ics", command, &PostIcsPayload { hook: "post_ics", files }) {
+            eprintln!("{}", e.to_string().red());
+        }
+    }
+    Ok(())
+}
+
+// ICS file and feeds, the free/busy file is left out because its window moves with every export
+fn ics_files(config: &Config) -> Vec<&PathBuf> {
+    std::iter::once(&config.ics_file).chain(config.feeds.values().map(|feed| &feed.file)).collect()
+}
+
+// Runs push_command if the ICS files changed since the last successful push
+fn push(config: &Config, force: bool, timeout: u64) -> Result<(), Error> {
+    let Some(command_str) = &config.push_command else {
+        return Ok(());
+    };
+    let state_file_path = config.state_file();
+    let mut state = load_state(&state_file_path)?;
+
+    let mut content = Vec::new();
+    for file in ics_files(config) {
+        content.extend(std::fs::read(file)?);
+    }
+    let hash = stable_hash(&content);
+    if !force && state.push.hash == hash {
+        eprintln!("{}", "ICS files unchanged since the last push, nothing to push.".green());
+        if state.push.pending.take().is_some() {
+            save_state(&state_file_path, &state)?;
+        }
+        return Ok(());
+    }
+
+    let uids: BTreeMap<String, String> = state.ics.iter().map(|(uid, revision)| (uid.clone(), revision.hash.clone())).collect();
+    let changed_uids = unpushed_uids(&state).iter().join(" ");
+
+    eprintln!("Executing: {}", command_str);
+    if let Err(e) = run_push_command(config, command_str, &changed_uids, timeout) {
+        // Queue the push, retries wait 1, 2, 4, ... minutes, at most an hour
+        let now = Utc::now();
+        let pending = state.push.pending.take();
+        let attempts = pending.as_ref().map_or(0, |pending| pending.attempts) + 1;
+        state.push.pending = Some(PendingPush {
+            since: pending.map_or(now, |pending| pending.since),
+            attempts,
+            last_error: e.to_string(),
+            next_retry: now + Duration::minutes(1 << (attempts - 1).min(6)).min(Duration::hours(1)),
+        });
+        save_state(&state_file_path, &state)?;
+        return Err(e);
+    }
+    state.push = PushState { hash, uids, pending: None };
+    save_state(&state_file_path, &state)
+}
+
+// UIDs of the events added, changed or deleted since the last successful push
+fn unpushed_uids(state: &State) -> Vec<&String> {
+    state
+        .ics
+        .iter()
+        .filter(|(uid, revision)| state.push.uids.get(*uid) != Some(&revision.hash))
+        .map(|(uid, _)| uid)
+        .chain(state.push.uids.keys().filter(|uid| !state.ics.contains_key(*uid)))
+        .collect()
+}
+
+// Pushes if a queued push is due for a retry, or after a change with auto_push
+fn retry_push(config: &Config, auto_push: bool) {
+    if config.push_command.is_none() {
+        return;
+    }
+    let pending = match load_state(&config.state_file()) {
+        Ok(state) => state.push.pending,
+        Err(e) => {
+            eprintln!("{}", format!("Failed to read the push state: {}", e).red());
+            return;
+        }
+    };
+    match pending {
+        Some(pending) if pending.next_retry > Utc::now() => return,
+        Some(pending) => eprintln!("{}", format!("Retrying failed push, attempt {}", pending.attempts + 1).yellow()),
+        None if !auto_push => return,
+        None => {}
+    }
+    // A retry shouldn't hold up the command, it only gets a short time unless the change is pushed anyway
+    let timeout = config.push_timeout.unwrap_or(60);
+    let timeout = if auto_push { timeout } else { timeout.min(RETRY_PUSH_TIMEOUT) };
+    // The change is saved already, a failing push is only reported
+    if let Err(e) = push(config, false, timeout) {
+        eprintln!("{}", format!("Push failed: {}", e).red());
+    }
+}
+
+// Whether changes are not pushed yet or the last push failed, shown as a marker by list and current like by push --status
+fn push_pending(config: &Config) -> bool {
+    if config.push_command.is_none() {
+        return false;
+    }
+    match load_state(&config.state_file()) {
+        Ok(state) => state.push.pending.is_some() || !unpushed_uids(&state).is_empty(),
+        Err(e) => {
+            eprintln!("{}", format!("Failed to read the push state: {}", e).red());
+            false
+        }
+    }
+}
+
+fn print_push_status(config: &Config, events: &[ScheduleEvent], timezone: &Tz) -> Result<(), Error> {
+    if config.push_command.is_none() {
+        println!("{}", "No push_command in config file".yellow());
+        return Ok(());
+    }
+    let state = load_state(&config.state_file())?;
+    let uids = unpushed_uids(&state);
+    match &state.push.pending {
+        Some(pending) => {
+            println!(
+                "{}",
+                format!(
+                    "Push pending since {}, {} failed attempts, next retry at {}",
+                    pending.since.with_timezone(timezone).format("%Y-%m-%d %H:%M"),
+                    pending.attempts,
+                    pending.next_retry.with_timezone(timezone).format("%Y-%m-%d %H:%M")
+                )
+                .red()
+            );
+            println!("Last error: {}", pending.last_error);
+        }
+        None if uids.is_empty() => {
+            println!("{}", "All changes are pushed.".green());
+            return Ok(());
+        }
+        None => println!("{}", "Not pushed yet:".yellow()),
+    }
+    for uid in uids {
+        match events.iter().find(|event| &event.id == uid) {
+            Some(event) => print_event(event, timezone),
+            None => println!("{} {}", "deleted".red(), uid),
+        }
+    }
+    Ok(())
+}
+
+// Seconds a push retried by other commands may take
+const RETRY_PUSH_TIMEOUT: u64 = 5;
+
+// Seconds a timed out push command gets to exit after TERM before it is killed
+const PUSH_KILL_GRACE: u64 = 5;
+
+// Stops a timed out command, with TERM to its process group first and KILL if it's still running after the grace period
+fn stop_command(child: &mut Child, process_group: bool) -> Result<(), Error> {
+    if !process_group {
+        child.kill()?;
+        child.wait()?;
+        return Ok(());
+    }
+    Command::new("sh").arg("-c").arg(format!("kill -TERM -{}", child.id())).status()?;
+    let stopped = Instant::now();
+    while stopped.elapsed().as_secs() < PUSH_KILL_GRACE {
+        if child.try_wait()?.is_some() {
+            return Ok(());
+        }
+        std::thread::sleep(std::time::Duration::from_millis(50));
+    }
+    Command::new("sh").arg("-c").arg(format!("kill -KILL -{}", child.id())).status()?;
+    child.wait()?;
+    Ok(())
+}
+
+fn run_push_command(config: &Config, command_str: &str, changed_uids: &str, timeout: u64) -> Result<(), Error> {
+    let mut command = Command::new("sh");
+    command
+        .arg("-c")
+        .arg(command_str)
+        .env("PLANTRACK_ICS_FILE", &config.ics_file)
+        .env("PLANTRACK_SCHEDULE_FILE", &config.schedule_file)
+        .env("PLANTRACK_CHANGED_UIDS", changed_uids)
+        .stdout(std::io::stderr())
+        .stderr(Stdio::piped());
+    // In its own process group the whole command is stopped on timeout, not only the shell. A background
+    // group can't read from the terminal though, so a command run from a terminal may prompt, e.g. for a password
+    let process_group = cfg!(unix) && !std::io::stdin().is_terminal();
+    #[cfg(unix)]
+    if process_group {
+        command.process_group(0);
+    }
+    let mut child = command.spawn()?;
+
+    // Read stderr while waiting, a full pipe would block the command
+    let mut stderr = child.stderr.take();
+    let stderr_reader = std::thread::spawn(move || {
+        let mut output = String::new();
+        if let Some(stderr) = stderr.as_mut() {
+            let _ = stderr.read_to_string(&mut output);
+        }
+        output
+    });
+
+    let started = Instant::now();
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if started.elapsed().as_secs() >= timeout {
+            stop_command(&mut child, process_group)?;
+            return Err(Error::new(ErrorKind::TimedOut, format!("Command timed out after {} seconds", timeout)));
+        }
+        std::thread::sleep(std::time::Duration::from_millis(50));
+    };
+    let stderr = stderr_reader.join().unwrap_or_default();
+
+    if !status.success() {
+        return Err(Error::other(format!("Command failed with exit code: {}\n{}", status, stderr.trim_end())));
+    }
+    eprint!("{}", stderr);
+    Ok(())
+}
+
+fn generate_freebusy(freebusy: &FreeBusyConfig, events: &[ScheduleEvent]) -> Result<(), Error> {
+    let mut calendar = ICalendar::new("2.0", "-//plantrack//plantrack version 1.0//EN");
+    let now = Utc::now().with_second(0).unwrap().with_nanosecond(0).unwrap();
+    let from = now - Duration::days(freebusy.past_days.unwrap_or(7));
+    let to = now + Duration::days(freebusy.future_days.unwrap_or(90));
+    let format = "%Y%m%dT%H%M%SZ";
+
+    let busy = busy_intervals(events, true, from, to);
+    let tentative = busy_intervals(events, false, from, to);
+    let intervals_count = busy.len() + tentative.len();
+
+    if freebusy.events {
+        // Plain events for clients without VFREEBUSY support, the UID only depends on the interval
+        for (start, end, status) in busy.iter().map(|(start, end)| (start, end, "CONFIRMED")).chain(tentative.iter().map(|(start, end)| (start, end, "TENTATIVE"))) {
+            let mut busy_event = Event::new(format!("busy-{}-{}@plantrack", start.timestamp(), end.timestamp()), now.format(format).to_string());
+            busy_event.push(Summary::new("Busy"));
+            busy_event.push(DtStart::new(start.format(format).to_string()));
+            busy_event.push(DtEnd::new(end.format(format).to_string()));
+            busy_event.push(Status::new(status));
+            calendar.add_event(busy_event);
+        }
+    } else {
+        let mut component = FreeBusy::new("freebusy@plantrack", now.format(format).to_string());
+        component.push(DtStart::new(from.format(format).to_string()));
+        component.push(DtEnd::new(to.format(format).to_string()));
+        for (intervals, fbtype) in [(&busy, "BUSY"), (&tentative, "BUSY-TENTATIVE")] {
+            for (start, end) in intervals {
+                let mut property = FreeBusyTime::new(format!("{}/{}", start.format(format), end.format(format)));
+                property.add(FBType::new(fbtype));
+                component.push(property);
+            }
+        }
+        calendar.add_freebusy(component);
+    }
+
+    calendar.save_file(&freebusy.file)?;
+    eprintln!("{} busy intervals exported to {}", intervals_count, freebusy.file.display());
+    Ok(())
+}
+
+fn format_utc_offset(seconds: i32) -> String {
+    let sign = if seconds < 0 { '-' } else { '+' };
+    let seconds = seconds.abs();
+    format!("{}{:02}{:02}", sign, seconds / 3600, seconds % 3600 / 60)
+}
+
+// VTIMEZONE with one definition per offset change within the exported period
+fn ics_timezone(timezone: Tz, from: DateTime<Utc>, to: DateTime<Utc>) -> TimeZone<'static> {
+    let offset_at = |time: DateTime<Utc>| timezone.offset_from_utc_datetime(&time.naive_utc());
+    let seconds_at = |time: DateTime<Utc>| offset_at(time).fix().local_minus_utc();
+
+    // Offset changes as (instant, offset before, offset after), found day by day and narrowed down to the second
+    let mut transitions = vec![(from, seconds_at(from), seconds_at(from))];
+    let mut day = from;
+    while day < to {
+        let next = day + Duration::days(1);
+        if seconds_at(next) != seconds_at(day) {
+            let (mut low, mut high) = (day, next);
+            while high - low > Duration::seconds(1) {
+                let middle = low + (high - low) / 2;
+                if seconds_at(middle) == seconds_at(low) {
+                    low = middle;
+                } else {
+                    high = middle;
+                }
+            }
+            transitions.push((high, seconds_at(low), seconds_at(high)));
+        }
+        day = next;
+    }
+
+    let mut definitions = transitions.into_iter().map(|(instant, before, after)| {
+        let offset = offset_at(instant);
+        let start = (instant.naive_utc() + Duration::seconds(before as i64)).format("%Y%m%dT%H%M%S").to_string();
+        let name = offset.abbreviation().map(|name| name.to_string());
+        (start, format_utc_offset(before), format_utc_offset(after), name, !offset.dst_offset().is_zero())
+    });
+
+    let (start, offset_from, offset_to, name, daylight) = definitions.next().unwrap();
+    let mut component = if daylight {
+        let mut definition = Daylight::new(start, offset_from, offset_to);
+        if let Some(name) = name {
+            definition.push(TzName::new(name));
+        }
+        TimeZone::daylight(timezone.name(), definition)
+    } else {
+        let mut definition = Standard::new(start, offset_from, offset_to);
+        if let Some(name) = name {
+            definition.push(TzName::new(name));
+        }
+        TimeZone::standard(timezone.name(), definition)
+    };
+    for (start, offset_from, offset_to, name, daylight) in definitions {
+        if daylight {
+            let mut definition = Daylight::new(start, offset_from, offset_to);
+            if let Some(name) = name {
+                definition.push(TzName::new(name));
+            }
+            component.add_daylight(definition);
+        } else {
+            let mut definition = Standard::new(start, offset_from, offset_to);
+            if let Some(name) = name {
+                definition.push(TzName::new(name));
+            }
+            component.add_standard(definition);
+        }
+    }
+    component
+}
+
+// Increments the SEQUENCE of every changed event, returns whether any revision changed
+fn update_revisions(revisions: &mut BTreeMap<String, IcsRevision>, events: &[ScheduleEvent]) -> bool {
+    let now = Utc::now().with_nanosecond(0).unwrap();
+    let ids: HashSet<&str> = events.iter().map(|event| event.id.as_str()).collect();
+    let count = revisions.len();
+    revisions.retain(|id, _| ids.contains(id.as_str()));
+    let mut changed = revisions.len() != count;
+
+    for event in events {
+        let hash = event_hash(event);
+        match revisions.get_mut(&event.id) {
+            Some(revision) if revision.hash == hash => {}
+            Some(revision) => {
+                revision.sequence += 1;
+                revision.hash = hash;
+                revision.last_modified = now;
+                changed = true;
+            }
+            None => {
+                revisions.insert(event.id.clone(), IcsRevision { hash, sequence: 0, last_modified: now });
+                changed = true;
+            }
+        }
+    }
+    changed
+}
+
+fn ics_calendar(events: &[ScheduleEvent], options: &IcsOptions, revisions: &BTreeMap<String, IcsRevision>) -> (ICalendar<'static>, usize) {
+    let mut calendar = ICalendar::new("2.0", "-//plantrack//plantrack version 1.0//EN");
+    calendar.push(Property::new("X-WR-CALNAME", options.calendar_name.to_string()));
+    if let Some(timezone) = options.timezone {
+        calendar.push(Property::new("X-WR-TIMEZONE", timezone.name()));
+    }
+
+    let now = Utc::now().with_second(0).unwrap().with_nanosecond(0).unwrap();
+    let past_cutoff = now - Duration::days(options.past_days);
+    let exported_events: Vec<Event> = exported_events(events, options).into_iter().map(|(event, rule)| ics_event(event, options, rule, revisions.get(&event.id))).collect();
+
+    let exported = events.iter().filter(|event| event.start_time >= past_cutoff);
+    if let (Some(timezone), Some(from), Some(to)) = (options.timezone, exported.clone().map(|event| event.start_time).min(), exported.map(|event| event.end_time).max()) {
+        calendar.add_timezone(ics_timezone(timezone, from, to));
+    }
+    let exported_events_count = exported_events.len();
+    for event in exported_events {
+        calendar.add_event(event);
+    }
+    (calendar, exported_events_count)
+}
+
+struct IcsEvent {
+    uid: String,
+    start_time: DateTime<Utc>,
+    end_time: DateTime<Utc>,
+    summary: String,
+    status: Option<String>,
+    location: Option<String>,
+    description: Option<String>,
+    categories: Vec<String>,
+    /// Timezone of DTSTART, recurrences are expanded in local time.
+    zone: Tz,
+    rrule: Option<String>,
+    exdates: Vec<DateTime<Utc>>,
+    recurrence_id: Option<DateTime<Utc>>,
+}
+
+fn unescape_ics_text(value: &str) -> String {
+    let mut result = String::new();
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            match chars.next() {
+                Some('n') | Some('N') => result.push('\n'),
+                Some(other) => result.push(other),
+                None => {}
+            }
+        } else {
+            result.push(c);
+        }
+    }
+    result
+}
+
+// Parses DATE-TIME values in UTC, with TZID or floating (interpreted in the given timezone)
+fn parse_ics_datetime(params: &str, value: &str, timezone: &Tz) -> Option<DateTime<Utc>> {
+    if let Some(utc) = value.strip_suffix('Z') {
+        return NaiveDateTime::parse_from_str(utc, "%Y%m%dT%H%M%S").ok().map(|naive| naive.and_utc());
+    }
+    let naive = NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%S").ok()?;
+    ics_zone(params, value, timezone).from_local_datetime(&naive).earliest().map(|datetime| datetime.with_timezone(&Utc))
+}
+
+fn ics_zone(params: &str, value: &str, timezone: &Tz) -> Tz {
+    if value.ends_with('Z') {
+        return Tz::UTC;
+    }
+    params
+        .split(';')
+        .find_map(|param| param.strip_prefix("TZID="))
+        .and_then(|tzid| tzid.trim_matches('"').parse().ok())
+        .unwrap_or(*timezone)
+}
+
+// Parses a DURATION value like PT1H30M or P1D
+fn parse_ics_duration(value: &str) -> Option<Duration> {
+    let (negative, value) = match value.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, value.trim_start_matches('+')),
+    };
+    let value = value.strip_prefix('P')?;
+    let mut duration = Duration::zero();
+    let mut number = String::new();
+    for c in value.chars() {
+        match c {
+            '0'..='9' => number.push(c),
+            'T' => {}
+            'W' | 'D' | 'H' | 'M' | 'S' => {
+                let amount: i64 = number.parse().ok()?;
+                number.clear();
+                duration += match c {
+                    'W' => Duration::weeks(amount),
+                    'D' => Duration::days(amount),
+                    'H' => Duration::hours(amount),
+                    'M' => Duration::minutes(amount),
+                    _ => Duration::seconds(amount),
+                };
+            }
+            _ => return None,
+        }
+    }
+    Some(if negative { -duration } else { duration })
+}
+
+// Reads the VEVENTs of an ICS file, all day events are skipped
+fn parse_ics(content: &str, timezone: &Tz) -> Vec<IcsEvent> {
+    let unfolded = content.replace("\r\n", "\n").replace("\n ", "").replace("\n\t", "");
+    let mut events = Vec::new();
+    let mut properties: Option<Vec<(String, String, String)>> = None;
+
+    for line in unfolded.lines() {
+        match line.trim_end() {
+            "BEGIN:VEVENT" => properties = Some(Vec::new()),
+            "END:VEVENT" => {
+                let Some(props) = properties.take() else { continue };
+                let get = |name: &str| props.iter().find(|(key, _, _)| key == name);
+                let start = get("DTSTART").and_then(|(_, params, value)| parse_ics_datetime(params, value, timezone));
+                let end = get("DTEND")
+                    .and_then(|(_, params, value)| parse_ics_datetime(params, value, timezone))
+                    .or_else(|| start.zip(get("DURATION").and_then(|(_, _, value)| parse_ics_duration(value))).map(|(start, duration)| start + duration));
+                if let (Some((_, _, uid)), Some(start_time), Some(end_time)) = (get("UID"), start, end) {
+                    events.push(IcsEvent {
+                        uid: uid.clone(),
+                        start_time,
+                        end_time,
+                        summary: get("SUMMARY").map(|(_, _, value)| unescape_ics_text(value)).unwrap_or_default(),
+                        status: get("STATUS").map(|(_, _, value)| value.to_uppercase()),
+                        location: get("LOCATION").map(|(_, _, value)| unescape_ics_text(value)).filter(|value| !value.is_empty()),
+                        description: get("DESCRIPTION").map(|(_, _, value)| unescape_ics_text(value)).filter(|value| !value.is_empty()),
+                        categories: props
+                            .iter()
+                            .filter(|(key, _, _)| key == "CATEGORIES")
+                            .flat_map(|(_, _, value)| value.split(',').map(normalize_tag).collect::<Vec<_>>())
+                            .filter(|tag| !tag.is_empty())
+                            .collect(),
+                        zone: get("DTSTART").map_or(*timezone, |(_, params, value)| ics_zone(params, value, timezone)),
+                        rrule: get("RRULE").map(|(_, _, value)| value.to_uppercase()),
+                        exdates: props
+                            .iter()
+                            .filter(|(key, _, _)| key == "EXDATE")
+                            .flat_map(|(_, params, value)| value.split(',').filter_map(|value| parse_ics_datetime(params, value, timezone)).collect::<Vec<_>>())
+                            .collect(),
+                        recurrence_id: get("RECURRENCE-ID").and_then(|(_, params, value)| parse_ics_datetime(params, value, timezone)),
+                    });
+                }
+            }
+            line => {
+                if let Some(props) = properties.as_mut() {
+                    if let Some((name, value)) = line.split_once(':') {
+                        let (key, params) = name.split_once(';').unwrap_or((name, ""));
+                        props.push((key.to_uppercase(), params.to_string(), value.to_string()));
+                    }
+                }
+            }
+        }
+    }
+    events
+}
+
+// Whether expand_recurrence understands every part of the rule, others would give wrong occurrences
+fn supported_rrule(rule: &HashMap<&str, &str>) -> bool {
+    let freq = rule.get("FREQ").copied();
+    let plain_weeks = !rule.contains_key("BYDAY") || rule.get("INTERVAL").is_none_or(|interval| *interval == "1");
+    matches!(freq, Some("DAILY" | "WEEKLY" | "MONTHLY" | "YEARLY"))
+        && rule.iter().all(|(name, value)| match *name {
+            "FREQ" | "INTERVAL" | "COUNT" | "UNTIL" => true,
+            // Weeks start on monday
+            "WKST" => *value == "MO" || plain_weeks,
+            // Only weekdays of weekly rules, no 1MO or BYDAY of monthly and yearly rules
+            "BYDAY" => freq == Some("WEEKLY") && value.split(',').all(|day| ["MO", "TU", "WE", "TH", "FR", "SA", "SU"].contains(&day)),
+            _ => false,
+        })
+}
+
+// Occurrences of a recurring event up to the given time, None if the rule is not supported
+fn expand_recurrence(event: &IcsEvent, until: DateTime<Utc>) -> Option<Vec<(DateTime<Utc>, DateTime<Utc>)>> {
+    let Some(rrule) = &event.rrule else {
+        return Some(vec![(event.start_time, event.end_time)]);
+    };
+    let rule: HashMap<&str, &str> = rrule.split(';').filter_map(|part| part.split_once('=')).collect();
+    if !supported_rrule(&rule) {
+        return None;
+    }
+    let interval = rule.get("INTERVAL").and_then(|interval| interval.parse::<i64>().ok()).unwrap_or(1).max(1);
+    let count = rule.get("COUNT").and_then(|count| count.parse::<usize>().ok());
+    let rule_until = rule.get("UNTIL").and_then(|value| {
+        parse_ics_datetime("", value, &event.zone).or_else(|| {
+            NaiveDate::parse_from_str(value, "%Y%m%d").ok().map(|date| date.and_hms_opt(23, 59, 59).unwrap().and_utc())
+        })
+    });
+    let until = rule_until.map_or(until, |rule_until| rule_until.min(until));
+    let weekdays: Vec<Weekday> = rule
+        .get("BYDAY")
+        .map(|days| {
+            days.split(',')
+                .filter_map(|day| match day {
+                    "MO" => Some(Weekday::Mon),
+                    "TU" => Some(Weekday::Tue),
+                    "WE" => Some(Weekday::Wed),
+                    "TH" => Some(Weekday::Thu),
+                    "FR" => Some(Weekday::Fri),
+                    "SA" => Some(Weekday::Sat),
+                    "SU" => Some(Weekday::Sun),
+                    _ => None,
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let duration = event.end_time - event.start_time;
+    let start = event.start_time.with_timezone(&event.zone).naive_local();
+    let mut occurrences = Vec::new();
+    let mut generated = 0;
+
+    for period in 0..10000 {
+        let candidates: Vec<NaiveDateTime> = match rule.get("FREQ").copied() {
+            Some("DAILY") => vec![start + Duration::days(period * interval)],
+            Some("WEEKLY") if !weekdays.is_empty() => {
+                let week_start = start - Duration::days(start.weekday().num_days_from_monday() as i64) + Duration::weeks(period * interval);
+                weekdays
+                    .iter()
+                    .map(|weekday| week_start + Duration::days(weekday.num_days_from_monday() as i64))
+                    .filter(|candidate| *candidate >= start)
+                    .sorted()
+                    .collect()
+            }
+            Some("WEEKLY") => vec![start + Duration::weeks(period * interval)],
+            Some("MONTHLY") => start.checked_add_months(chrono::Months::new((period * interval) as u32)).filter(|candidate| candidate.day() == start.day()).into_iter().collect(),
+            Some("YEARLY") => start.with_year(start.year() + (period * interval) as i32).into_iter().collect(),
+            _ => return None,
+        };
+
+        for candidate in candidates {
+            let Some(candidate) = event.zone.from_local_datetime(&candidate).earliest().map(|time| time.with_timezone(&Utc)) else { continue };
+            if candidate > until || count.is_some_and(|count| generated >= count) {
+                return Some(occurrences);
+            }
+            generated += 1;
+            if !event.exdates.contains(&candidate) {
+                occurrences.push((candidate, candidate + duration));
+            }
+        }
+    }
+    Some(occurrences)
+}
+
+// Events of the read-only overlay calendars, a missing or unreadable file only prints a warning
+fn load_overlays(overlays: &BTreeMap<String, PathBuf>, timezone: &Tz) -> Vec<ScheduleEvent> {
+    let until = Utc::now() + Duration::days(366);
+    let mut overlay_events = Vec::new();
+    for (name, file_path) in overlays {
+        let content = match std::fs::read_to_string(file_path) {
+            Ok(content) => content,
+            Err(e) => {
+                eprintln!("{}", format!("Skipping overlay {} ({}): {}", name, file_path.display(), e).yellow());
+                continue;
+            }
+        };
+        let ics_events = parse_ics(&content, timezone);
+        // Modified occurrences replace the occurrence of the recurring event
+        let overridden: HashSet<(&str, DateTime<Utc>)> = ics_events
+            .iter()
+            .filter_map(|event| event.recurrence_id.map(|recurrence_id| (event.uid.as_str(), recurrence_id)))
+            .collect();
+
+        for event in ics_events.iter().filter(|event| event.status.as_deref() != Some("CANCELLED")) {
+            let occurrences = expand_recurrence(event, until).unwrap_or_else(|| {
+                let rrule = event.rrule.as_deref().unwrap_or_default();
+                eprintln!("{}", format!("Overlay {}: recurrence {} of {} is not supported, only the first occurrence is used", name, rrule, event.summary).yellow());
+                vec![(event.start_time, event.end_time)]
+            });
+            for (start_time, end_time) in occurrences {
+                if event.recurrence_id.is_none() && overridden.contains(&(event.uid.as_str(), start_time)) {
+                    continue;
+                }
+                overlay_events.push(ScheduleEvent {
+                    id: if event.rrule.is_some() { format!("{}/{}", event.uid, start_time.timestamp()) } else { event.uid.clone() },
+                    start_time,
+                    end_time,
+                    summary: event.summary.clone(),
+                    note: event.description.clone(),
+                    location: event.location.clone(),
+                    booked: event.status.as_deref() != Some("TENTATIVE"),
+                    tags: event.categories.clone(),
+                    planned_ahead: None,
+                    overlay: Some(name.clone()),
+                });
+            }
+        }
+    }
+    overlay_events.sort_by_key(|event| event.start_time);
+    overlay_events
+}
+
+// Prints the overlay events overlapping the new event, returns whether there are any
+fn print_overlay_conflicts(overlay_events: &[ScheduleEvent], event: &ScheduleEvent, timezone: &Tz) -> bool {
+    match is_slot_free(overlay_events, event.start_time, event.end_time) {
+        Ok(_) => false,
+        Err(conflicting_events) => {
+            println!("{}", "Overlapping overlay events:".yellow().bold());
+            for conflicting_event in &conflicting_events {
+                print_event(conflicting_event, timezone);
+            }
+            println!();
+            true
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Default)]
+struct State {
+    #[serde(default)]
+    caldav: BTreeMap<String, SyncEntry>,
+    /// SEQUENCE and LAST-MODIFIED of the exported events.
+    #[serde(default)]
+    ics: BTreeMap<String, IcsRevision>,
+    #[serde(default)]
+    push: PushState,
+}
+
+// What was published by the last successful push
+#[derive(Deserialize, Serialize, Debug, Default)]
+struct PushState {
+    /// Hash of the ICS files.
+    hash: String,
+    /// Revision hash per UID, to tell which events changed since.
+    uids: BTreeMap<String, String>,
+    /// Set while the last push failed.
+    #[serde(default)]
+    pending: Option<PendingPush>,
+}
+
+// A failed push, retried with backoff by the next invocation
+#[derive(Deserialize, Serialize, Debug, Clone)]
+struct PendingPush {
+    #[serde(with = "chrono::serde::ts_seconds")]
+    since: DateTime<Utc>,
+    attempts: u32,
+    last_error: String,
+    #[serde(with = "chrono::serde::ts_seconds")]
+    next_retry: DateTime<Utc>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+struct IcsRevision {
+    hash: String,
+    sequence: u32,
+    #[serde(with = "chrono::serde::ts_seconds")]
+    last_modified: DateTime<Utc>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+struct SyncEntry {
+    href: String,
+    etag: Option<String>,
+    hash: String,
+    #[serde(with = "chrono::serde::ts_seconds")]
+    end_time: DateTime<Utc>,
+}
+
+fn load_state(file_path: &PathBuf) -> Result<State, Error> {
+    match File::open(file_path) {
+        Ok(file) => serde_json::from_reader(file).map_err(|e| {
+            Error::new(ErrorKind::InvalidData, format!("Failed to parse state file: {}", e))
+        }),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(State::default()),
+        Err(e) => Err(e),
+    }
+}
+
+fn save_state(file_path: &PathBuf, state: &State) -> Result<(), Error> {
+    let file = File::create(file_path)?;
+    serde_json::to_writer_pretty(file, state)?;
+    Ok(())
+}
+
+// FNV-1a, stable across Rust versions unlike the std hasher
+fn stable_hash(data: &[u8]) -> String {
+    let hash = data.iter().fold(0xcbf29ce484222325u64, |hash, byte| (hash ^ *byte as u64).wrapping_mul(0x100000001b3));
+    format!("{:016x}", hash)
+}
+
+fn event_hash(event: &ScheduleEvent) -> String {
+    stable_hash(&serde_json::to_vec(event).unwrap_or_default())
+}
+
+// Hash of what is uploaded, so changes of the export rules are synced, hidden changes are not
+fn sync_hash(event: &ScheduleEvent, options: &IcsOptions) -> String {
+    event_hash(&shared_event(event, options, export_rule(options.rules, &event.summary)))
+}
+
+struct CaldavClient {
+    url: String,
+    authorization: Option<String>,
+}
+
+struct CaldavResponse {
+    status: u16,
+    etag: Option<String>,
+    body: String,
+}
+
+impl CaldavClient {
+    fn new(caldav: &CaldavConfig) -> Result<Self, Error> {
+        let password = match (&caldav.password, &caldav.password_command) {
+            (Some(password), _) => Some(password.clone()),
+            (None, Some(command)) => {
+                let output = Command::new("sh").arg("-c").arg(command).output()?;
+                if !output.status.success() {
+                    return Err(Error::other(format!("password_command failed with exit code: {}", output.status)));
+                }
+                Some(String::from_utf8_lossy(&output.stdout).trim().to_string())
+            }
+            (None, None) => None,
+        };
+        let authorization = caldav.username.as_ref().map(|username| {
+            let credentials = format!("{}:{}", username, password.unwrap_or_default());
+            format!("Basic {}", base64::engine::general_purpose::STANDARD.encode(credentials))
+        });
+        let url = if caldav.url.ends_with('/') { caldav.url.clone() } else { format!("{}/", caldav.url) };
+        Ok(Self { url, authorization })
+    }
+
+    // Resolves an href of a multistatus response against the collection URL
+    fn resolve(&self, href: &str) -> String {
+        if href.starts_with("http://") || href.starts_with("https://") {
+            href.to_string()
+        } else if href.starts_with('/') {
+            let origin_end = self.url.find("://").map(|scheme| scheme + 3).and_then(|host| self.url[host..].find('/').map(|path| host + path));
+            format!("{}{}", &self.url[..origin_end.unwrap_or(self.url.len())], href)
+        } else {
+            format!("{}{}", self.url, href)
+        }
+    }
+
+    fn send(&self, method: &str, url: &str, headers: &[(&str, &str)], body: &str) -> Result<CaldavResponse, Error> {
+        let mut request = ureq::request(method, url);
+        if let Some(authorization) = &self.authorization {
+            request = request.set("Authorization", authorization);
+        }
+        for (name, value) in headers {
+            request = request.set(name, value);
+        }
+        let response = match request.send_string(body) {
+            Ok(response) => response,
+            Err(ureq::Error::Status(_, response)) => response,
+            Err(e) => return Err(Error::other(format!("{} {} failed: {}", method, url, e))),
+        };
+        let status = response.status();
+        let etag = response.header("ETag").map(|etag| etag.to_string());
+        let body = response.into_string()?;
+        if status == 401 || status == 403 {
+            return Err(Error::new(ErrorKind::PermissionDenied, format!("{} {} failed with status {}", method, url, status)));
+        }
+        Ok(CaldavResponse { status, etag, body })
+    }
+
+    // Fetches all events of the collection ending after the given time
+    fn fetch(&self, from: DateTime<Utc>, timezone: &Tz) -> Result<Vec<(String, Option<String>, IcsEvent)>, Error> {
+        let body = format!(
+            r#"<?xml version="1.0" encoding="utf-8" ?>
+<C:calendar-query xmlns:D="DAV:" xmlns:C="urn:ietf:params:xml:ns:caldav">
+  <D:prop><D:getetag/><C:calendar-data/></D:prop>
+  <C:filter>
+    <C:comp-filter name="VCALENDAR">
+      <C:comp-filter name="VEVENT"><C:time-range start="{}"/></C:comp-filter>
+    </C:comp-filter>
+  </C:filter>
+</C:calendar-query>"#,
+            from.format("%Y%m%dT%H%M%SZ")
+        );
+        let response = self.send("REPORT", &self.url, &[("Depth", "1"), ("Content-Type", "application/xml; charset=utf-8")], &body)?;
+        if response.status != 207 {
+            return Err(Error::other(format!("REPORT {} failed with status {}", self.url, response.status)));
+        }
+
+        let mut remote = Vec::new();
+        for element in xml_elements(&response.body, "response") {
+            let Some(href) = xml_elements(element, "href").first().map(|href| xml_unescape(href.trim())) else { continue };
+            let etag = xml_elements(element, "getetag").first().map(|etag| xml_unescape(etag.trim())).filter(|etag| !etag.is_empty());
+            let Some(data) = xml_elements(element, "calendar-data").first().map(|data| xml_unescape(data)) else { continue };
+            for event in parse_ics(&data, timezone) {
+                remote.push((href.clone(), etag.clone(), event));
+            }
+        }
+        Ok(remote)
+    }
+}
+
+// Inner content of all elements with the given local name, regardless of the namespace prefix
+fn xml_elements<'a>(xml: &'a str, local_name: &str) -> Vec<&'a str> {
+    let mut elements = Vec::new();
+    let mut position = 0;
+    while let Some(offset) = xml[position..].find('<') {
+        let start = position + offset + 1;
+        let Some(tag_end) = xml[start..].find('>').map(|end| start + end) else { break };
+        let tag = &xml[start..tag_end];
+        let name = tag.split(|c: char| c.is_whitespace() || c == '/').next().unwrap_or_default();
+        position = tag_end + 1;
+        if name.starts_with(['/', '?', '!']) || name.rsplit(':').next() != Some(local_name) {
+            continue;
+        }
+        if tag.ends_with('/') {
+            elements.push("");
+            continue;
+        }
+        let closing = format!("</{}>", name);
+        if let Some(end) = xml[position..].find(&closing) {
+            elements.push(&xml[position..position + end]);
+            position += end + closing.len();
+        }
+    }
+    elements
+}
+
+fn xml_unescape(value: &str) -> String {
+    let value = value.trim();
+    if let Some(cdata) = value.strip_prefix("<![CDATA[").and_then(|rest| rest.strip_suffix("]]>")) {
+        return cdata.to_string();
+    }
+    value
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&#13;", "\r")
+        .replace("&#xD;", "\r")
+        .replace("&amp;", "&")
+}
+
+enum SyncAction {
+    Upload(ScheduleEvent, Option<SyncEntry>),
+    DeleteRemote(String, SyncEntry),
+    Import(ScheduleEvent, String, Option<String>),
+    DeleteLocal(ScheduleEvent),
+    Conflict(ScheduleEvent, String),
+}
+
+// Local event from a remote event, keeping the project:task and planning of a known event
+fn event_from_remote(remote: &IcsEvent, local: Option<&ScheduleEvent>, options: &IcsOptions) -> ScheduleEvent {
+    let booked = remote.status.as_deref() == Some("CONFIRMED");
+    let summary = match local {
+        Some(local) => local.summary.clone(),
+        None => parse_project_path(&remote.summary).unwrap_or_else(|_| format!("{}:imported", remote.summary.replace(':', " ").trim())),
+    };
+    // What the export rules don't upload is kept from the local event
+    let rule = local.and_then(|local| export_rule(options.rules, &local.summary));
+    let note = match local {
+        Some(local) if !rule.and_then(|rule| rule.notes).unwrap_or(options.export_notes) => local.note.clone(),
+        _ => remote.description.clone(),
+    };
+    let location = match local {
+        Some(local) if !rule.and_then(|rule| rule.location).unwrap_or(true) => local.location.clone(),
+        _ => remote.location.clone(),
+    };
+    let tags = match (local, rule.and_then(|rule| rule.categories.as_ref())) {
+        (Some(local), Some(_)) => local.tags.clone(),
+        _ => collect_tags(&[], &remote.categories, None),
+    };
+    ScheduleEvent {
+        id: remote.uid.clone(),
+        start_time: remote.start_time,
+        end_time: remote.end_time,
+        summary,
+        note,
+        location,
+        booked,
+        tags,
+        planned_ahead: local.map(|local| local.planned_ahead).unwrap_or(Some(!booked)),
+        overlay: None,
+    }
+}
+
+fn plan_sync(events: &[ScheduleEvent], remote: &[(String, Option<String>, IcsEvent)], state: &State, window_start: DateTime<Utc>, prefer: Option<SyncPrefer>, options: &IcsOptions) -> Vec<SyncAction> {
+    let mut actions = Vec::new();
+    let remote_by_uid: HashMap<&str, &(String, Option<String>, IcsEvent)> = remote.iter().map(|entry| (entry.2.uid.as_str(), entry)).collect();
+    // Events hidden by the export rules are not synced, and deleted remotely if they were
+    let (events, hidden): (Vec<&ScheduleEvent>, Vec<&ScheduleEvent>) = events.iter().partition(|event| export_rule(options.rules, &event.summary).and_then(|rule| rule.export).unwrap_or(true));
+    let hidden_ids: HashSet<&str> = hidden.iter().map(|event| event.id.as_str()).collect();
+
+    for event in events.iter().copied().filter(|event| event.end_time >= window_start) {
+        let entry = state.caldav.get(&event.id);
+        let remote_entry = remote_by_uid.get(event.id.as_str());
+        let local_changed = entry.is_none_or(|entry| entry.hash != sync_hash(event, options));
+
+        match (entry, remote_entry) {
+            (None, None) => actions.push(SyncAction::Upload(event.clone(), None)),
+            (None, Some((href, etag, remote_event))) => {
+                // Known on both sides without sync state, e.g. after losing the state file
+                match prefer {
+                    Some(SyncPrefer::Remote) => actions.push(SyncAction::Import(event_from_remote(remote_event, Some(event), options), href.clone(), etag.clone())),
+                    _ => actions.push(SyncAction::Upload(event.clone(), Some(SyncEntry { href: href.clone(), etag: etag.clone(), hash: String::new(), end_time: event.end_time }))),
+                }
+            }
+            (Some(entry), Some((href, etag, remote_event))) => {
+                let remote_changed = etag.is_some() && entry.etag != *etag;
+                let current = SyncEntry { href: href.clone(), etag: etag.clone(), ..entry.clone() };
+                match (local_changed, remote_changed, prefer) {
+                    (false, false, _) => {}
+                    (true, false, _) | (true, true, Some(SyncPrefer::Local)) => actions.push(SyncAction::Upload(event.clone(), Some(current))),
+                    (false, true, _) | (true, true, Some(SyncPrefer::Remote)) => {
+                        actions.push(SyncAction::Import(event_from_remote(remote_event, Some(event), options), href.clone(), etag.clone()))
+                    }
+                    (true, true, None) => actions.push(SyncAction::Conflict(event.clone(), "changed locally and remotely".to_string())),
+                }
+            }
+            (Some(entry), None) => match (local_changed, prefer) {
+                (false, _) | (true, Some(SyncPrefer::Remote)) => actions.push(SyncAction::DeleteLocal(event.clone())),
+                (true, Some(SyncPrefer::Local)) => actions.push(SyncAction::Upload(event.clone(), Some(SyncEntry { etag: None, ..entry.clone() }))),
+                (true, None) => actions.push(SyncAction::Conflict(event.clone(), "changed locally and deleted remotely".to_string())),
+            },
+        }
+    }
+
+    let local_ids: HashMap<&str, &ScheduleEvent> = events.iter().map(|event| (event.id.as_str(), *event)).collect();
+    for (uid, entry) in &state.caldav {
+        if local_ids.contains_key(uid.as_str()) || entry.end_time < window_start {
+            continue;
+        }
+        match remote_by_uid.get(uid.as_str()) {
+            Some((href, etag, _)) if hidden_ids.contains(uid.as_str()) => actions.push(SyncAction::DeleteRemote(uid.clone(), SyncEntry { href: href.clone(), etag: etag.clone(), ..entry.clone() })),
+            Some((href, etag, remote_event)) if etag.is_some() && entry.etag != *etag => {
+                // Deleted locally, but changed remotely
+                match prefer {
+                    Some(SyncPrefer::Local) => actions.push(SyncAction::DeleteRemote(uid.clone(), SyncEntry { href: href.clone(), etag: etag.clone(), ..entry.clone() })),
+                    Some(SyncPrefer::Remote) => actions.push(SyncAction::Import(event_from_remote(remote_event, None, options), href.clone(), etag.clone())),
+                    None => actions.push(SyncAction::Conflict(event_from_remote(remote_event, None, options), "deleted locally and changed remotely".to_string())),
+                }
+            }
+            Some(_) => actions.push(SyncAction::DeleteRemote(uid.clone(), entry.clone())),
+            None => {}
+        }
+    }
+
+    for (href, etag, remote_event) in remote {
+        if hidden_ids.contains(remote_event.uid.as_str()) && !state.caldav.contains_key(&remote_event.uid) {
+            let entry = SyncEntry { href: href.clone(), etag: etag.clone(), hash: String::new(), end_time: remote_event.end_time };
+            actions.push(SyncAction::DeleteRemote(remote_event.uid.clone(), entry));
+        } else if !local_ids.contains_key(remote_event.uid.as_str()) && !state.caldav.contains_key(&remote_event.uid) {
+            actions.push(SyncAction::Import(event_from_remote(remote_event, None, options), href.clone(), etag.clone()));
+        }
+    }
+    actions
+}
+
+fn print_sync_actions(actions: &[SyncAction], events: &[ScheduleEvent], timezone: &Tz) {
+    println!("{}", "Sync changes:".yellow().bold());
+    for action in actions {
+        match action {
+            SyncAction::Upload(event, _) => println!("↑ {}", format_event_for_diff(event, timezone).green()),
+            SyncAction::DeleteRemote(uid, _) => println!("↑ {}", format!("delete remote event {}", uid).red()),
+            SyncAction::Import(event, _, _) => match events.iter().find(|local| local.id == event.id) {
+                Some(local) => println!("↓ {}", format_event_change_for_diff(local, event, timezone).yellow()),
+                None => println!("↓ {}", format_event_for_diff(event, timezone).green()),
+            },
+            SyncAction::DeleteLocal(event) => println!("↓ {}", format_event_for_diff(event, timezone).red()),
+            SyncAction::Conflict(event, reason) => println!("! {} ({})", format_event_for_diff(event, timezone).bright_red(), reason),
+        }
+    }
+    println!();
+}
+
+// Actions which were not applied, they are planned again by the next sync
+#[derive(Default)]
+struct SyncOutcome {
+    conflicts: usize,
+    failed: usize,
+}
+
+// The local result of the sync, it doesn't depend on the requests to the server
+fn apply_local_sync(events: &mut Vec<ScheduleEvent>, actions: &[SyncAction]) {
+    for action in actions {
+        match action {
+            SyncAction::Import(event, _, _) => {
+                events.retain(|local| local.id != event.id);
+                events.push(event.clone());
+            }
+            SyncAction::DeleteLocal(event) => events.retain(|local| local.id != event.id),
+            _ => {}
+        }
+    }
+    events.sort_by_key(|event| event.start_time);
+}
+
+fn execute_sync(client: &CaldavClient, actions: Vec<SyncAction>, state: &mut State, options: &IcsOptions, window_start: DateTime<Utc>, timezone: &Tz) -> SyncOutcome {
+    let mut outcome = SyncOutcome::default();
+    for action in actions {
+        match action {
+            SyncAction::Upload(event, entry) => {
+                let mut calendar = ICalendar::new("2.0", "-//plantrack//plantrack version 1.0//EN");
+                if let Some(timezone) = options.timezone {
+                    calendar.add_timezone(ics_timezone(timezone, event.start_time, event.end_time));
+                }
+                calendar.add_event(ics_event(&event, options, export_rule(options.rules, &event.summary), state.ics.get(&event.id)));
+                let href = entry.as_ref().map(|entry| entry.href.clone()).unwrap_or_else(|| format!("{}.ics", event.id));
+                let url = client.resolve(&href);
+                let condition = match entry.as_ref().and_then(|entry| entry.etag.clone()) {
+                    Some(etag) => ("If-Match", etag),
+                    None if entry.is_some() => ("If-Match", "*".to_string()),
+                    None => ("If-None-Match", "*".to_string()),
+                };
+                let response = match client.send("PUT", &url, &[("Content-Type", "text/calendar; charset=utf-8"), (condition.0, &condition.1)], &calendar.to_string()) {
+                    Ok(response) => response,
+                    Err(e) => {
+                        println!("{}", format!("Upload of {} failed: {}", event.id, e).red());
+                        outcome.failed += 1;
+                        continue;
+                    }
+                };
+                if response.status == 412 {
+                    println!("{}", format!("Conflict: {} was changed remotely, not uploaded", event.id).red());
+                    outcome.conflicts += 1;
+                } else if !(200..300).contains(&response.status) {
+                    println!("{}", format!("Upload of {} failed with status {}", event.id, response.status).red());
+                    outcome.failed += 1;
+                } else {
+                    let path = if href.starts_with("http") || href.starts_with('/') { href } else { url };
+                    state.caldav.insert(event.id.clone(), SyncEntry { href: path, etag: response.etag, hash: sync_hash(&event, options), end_time: event.end_time });
+                }
+            }
+            SyncAction::DeleteRemote(uid, entry) => {
+                let url = client.resolve(&entry.href);
+                let if_match = entry.etag.clone().unwrap_or_else(|| "*".to_string());
+                let response = match client.send("DELETE", &url, &[("If-Match", &if_match)], "") {
+                    Ok(response) => response,
+                    Err(e) => {
+                        println!("{}", format!("Delete of {} failed: {}", uid, e).red());
+                        outcome.failed += 1;
+                        continue;
+                    }
+                };
+                if (200..300).contains(&response.status) || response.status == 404 {
+                    state.caldav.remove(&uid);
+                } else if response.status == 412 {
+                    println!("{}", format!("Conflict: {} was changed remotely, not deleted", uid).red());
+                    outcome.conflicts += 1;
+                } else {
+                    println!("{}", format!("Delete of {} failed with status {}", uid, response.status).red());
+                    outcome.failed += 1;
+                }
+            }
+            SyncAction::Import(event, href, etag) => {
+                state.caldav.insert(event.id.clone(), SyncEntry { href, etag, hash: sync_hash(&event, options), end_time: event.end_time });
+            }
+            SyncAction::DeleteLocal(event) => {
+                state.caldav.remove(&event.id);
+            }
+            SyncAction::Conflict(_, _) => outcome.conflicts += 1,
+        }
+    }
+
+    // Servers are not required to return an ETag on PUT, fetch the missing ones
+    if state.caldav.values().any(|entry| entry.etag.is_none() && entry.end_time >= window_start) {
+        match client.fetch(window_start, timezone) {
+            Ok(remote) => {
+                for (_, etag, remote_event) in remote {
+                    if let Some(entry) = state.caldav.get_mut(&remote_event.uid) {
+                        if entry.etag.is_none() {
+                            entry.etag = etag;
+                        }
+                    }
+                }
+            }
+            // Events without ETag are uploaded with If-Match: * next time
+            Err(e) => println!("{}", format!("Fetching the ETags failed: {}", e).yellow()),
+        }
+    }
+    outcome
+}
+
+const CALENDAR_HTML: &str = include_str!("../calendar.html");
+
+struct ServeOptions<'a> {
+    config: &'a Config,
+    credentials: Option<String>, // Base64 of username:password for basic authentication
+}
+
+// Serves the calendar page, the live ICS feed and the events as JSON, each connection in its own thread
+fn serve(bind: &str, options: &ServeOptions) -> Result<(), Error> {
+    let listener = TcpListener::bind(bind)?;
+    let address = listener.local_addr()?;
+    println!("Serving http://{}/ (calendar.html, schedule.ics, events.json)", address);
+    // A thread per connection, so a slow client doesn't block the others
+    std::thread::scope(|scope| {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    scope.spawn(move || {
+                        if let Err(e) = handle_request(stream, options) {
+                            eprintln!("{}", format!("Request failed: {}", e).red());
+                        }
+                    });
+                }
+                Err(e) => eprintln!("{}", format!("Request failed: {}", e).red()),
+            }
+        }
+    });
+    Ok(())
+}
+
+// Checks basic authentication, the scheme is case-insensitive and the credentials are compared in constant time
+fn is_authorized(authorization: Option<&str>, credentials: &str) -> bool {
+    let Some((scheme, token)) = authorization.and_then(|authorization| authorization.split_once(' ')) else {
+        return false;
+    };
+    let token = token.trim().as_bytes();
+    scheme.eq_ignore_ascii_case("basic") && token.len() == credentials.len() && token.iter().zip(credentials.as_bytes()).fold(0, |difference, (a, b)| difference | (a ^ b)) == 0
+}
+
+fn handle_request(mut stream: TcpStream, options: &ServeOptions) -> Result<(), Error> {
+    stream.set_read_timeout(Some(std::time::Duration::from_secs(10)))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    let mut authorization = None;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 || line.trim_end().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("authorization") {
+                authorization = Some(value.trim().to_string());
+            }
+        }
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or_default();
+    let target = parts.next().unwrap_or("/");
+    let path = target.split_once('?').map_or(target, |(path, _)| path);
+
+    let (status, content_type, body) = if options.credentials.as_ref().is_some_and(|credentials| !is_authorized(authorization.as_deref(), credentials)) {
+        ("401 Unauthorized", "text/plain; charset=utf-8", "Unauthorized".to_string())
+    } else if method != "GET" && method != "HEAD" {
+        ("405 Method Not Allowed", "text/plain; charset=utf-8", "Method not allowed".to_string())
+    } else {
+        match serve_path(path, options) {
+            Ok(Some((content_type, body))) => ("200 OK", content_type, body),
+            Ok(None) => ("404 Not Found", "text/plain; charset=utf-8", "Not found".to_string()),
+            Err(e) => ("500 Internal Server Error", "text/plain; charset=utf-8", e.to_string()),
+        }
+    };
+
+    let mut response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nCache-Control: no-cache\r\nConnection: close\r\n",
+        status,
+        content_type,
+        body.len()
+    );
+    if status.starts_with("401") {
+        response.push_str("WWW-Authenticate: Basic realm=\"plantrack\"\r\n");
+    }
+    response.push_str("\r\n");
+    if method != "HEAD" {
+        response.push_str(&body);
+    }
+    stream.write_all(response.as_bytes())?;
+    println!("{} {} {}", method, path, status);
+    Ok(())
+}
+
+// The schedule is read on every request, so changes show up without restarting the server
+fn serve_path(path: &str, options: &ServeOptions) -> Result<Option<(&'static str, String)>, Error> {
+    let config = options.config;
+    let ics_options = match path.strip_prefix("/feeds/").and_then(|name| name.strip_suffix(".ics")) {
+        Some(name) => config.feeds.get_key_value(name).map(|(name, feed)| config.feed_options(name, feed)),
+        None if path == "/schedule.ics" => Some(config.ics_options()),
+        None => None,
+    };
+    if let Some(ics_options) = ics_options {
+        // Revisions of events changed since the last export are only updated in memory
+        let events = load_events(&config.schedule_file)?;
+        let mut state = load_state(&config.state_file())?;
+        update_revisions(&mut state.ics, &events);
+        let (calendar, _) = ics_calendar(&events, &ics_options, &state.ics);
+        return Ok(Some(("text/calendar; charset=utf-8", calendar.to_string())));
+    }
+
+    match path {
+        "/" | "/calendar.html" => Ok(Some(("text/html; charset=utf-8", CALENDAR_HTML.to_string()))),
+        "/events.json" => {
+            // The same events as in schedule.ics
+            let events = load_events(&config.schedule_file)?;
+            let ics_options = config.ics_options();
+            let shared: Vec<ScheduleEvent> = exported_events(&events, &ics_options).into_iter().map(|(event, rule)| shared_event(event, &ics_options, rule)).collect();
+            Ok(Some(("application/json", serde_json::to_string(&shared)?)))
+        }
+        _ => Ok(None),
+    }
+}
+
+fn print_events_grouped_by_day(events: &[ScheduleEvent], timezone: &Tz, days: u32, date_str: Option<String>, past: bool) {
+    let now = if let Some(date_str) = date_str {
+        match NaiveDate::parse_from_str(&date_str, "%Y-%m-%d") {
+            Ok(date) => timezone.from_local_datetime(&date.and_hms_opt(0, 0, 0).unwrap()).unwrap(),
+            Err(_) => {
+                println!("{}", "Invalid date format. Using today.".yellow());
+                Utc::now().with_second(0).unwrap().with_nanosecond(0).unwrap().with_timezone(timezone)
+            }
+        }
+    } else {
+        Utc::now().with_second(0).unwrap().with_nanosecond(0).unwrap().with_timezone(timezone)
+    };
+    let realnow = Utc::now().with_second(0).unwrap().with_nanosecond(0).unwrap().with_timezone(timezone);
+    let day_range = if past {
+        -(days as i64)..=-1
+    } else {
+        0..=(days as i64)
+    };
+
+    for day_offset in day_range {
+        let current_date = now.date_naive() + Duration::days(day_offset);
+        let date_str = current_date.format("%Y-%m-%d - %a").to_string();
+        let date_str = if current_date == realnow.date_naive() {
+            date_str.bright_yellow().bold().to_string()
+        } else {
+            date_str.bright_blue().bold().to_string()
+        };
+        println!("{}", date_str);
+
+        let events_for_day: Vec<&ScheduleEvent> = events
+            .iter()
+            .filter(|event| {
+                let event_start_date = event.start_time.with_timezone(timezone).date_naive();
+                 event_start_date == current_date
+            })
+            .collect();
+
+        print_day_travel(&events_for_day);
+        if events_for_day.is_empty() {
+            println!("    {}", "No events".italic());
+        } else {
+            let mut last_end_time: Option<DateTime<Tz>> = None;
+            for event in events_for_day {
+                let start_time_local = event.start_time.with_timezone(timezone);
+
+                if let Some(last_et) = last_end_time {
+                    let free_time = start_time_local - last_et;
+                    if free_time > Duration::zero() {
+                        let now = Utc::now().with_second(0).unwrap().with_nanosecond(0).unwrap().with_timezone(timezone);
+                        if last_et <= now && now < start_time_local {
+                            let from_last_event = now - last_et;
+                            let til_next_event = start_time_local - now;
+                            println!("       {}                  {}",
+                                format_duration(from_last_event, true).bright_yellow().italic(),
+                                "⋮".bright_yellow());
+                            println!("  {}                        {} {}",
+                                "› ...".bright_yellow(),
+                                format_duration(free_time, true).bright_yellow(),
+                                "free".bright_yellow());
+                            println!("       {}                  {}",
+                                format_duration(til_next_event, true).bright_yellow().italic(),
+                                "⋮".bright_yellow());
+                        } else {
+                            println!("                               {}", "⋮".bright_green());
+                            println!("                               {} {}", format_duration(free_time, true).bright_green(), "free".bright_green());
+                            println!("                               {}", "⋮".bright_green());
+                        }
+                    }
+                }
+
+                print_event(event, timezone);
+                last_end_time = Some(event.end_time.with_timezone(timezone));
+                // print_event(event, timezone);
+            }
+        }
+        println!();
+    }
+}
+
+// Sequence of locations visited, consecutive events at the same location are collapsed
+fn travel_route(events: &[&ScheduleEvent]) -> Vec<String> {
+    let mut travel_info: Vec<String> = Vec::new();
+    let mut last_location: Option<String> = None;
+    for event in events {
+        if let Some(location) = &event.location {
+            if last_location.as_ref() != Some(location) {
+                if let Some(last_loc) = last_location {
+                    travel_info.push(last_loc);
+                }
+                last_location = Some(location.clone());
+            }
+        }
+    }
+    if let Some(last_loc) = last_location {
+        travel_info.push(last_loc);
+    }
+    travel_info
+}
+
+// Print travel information
+fn print_day_travel(events_for_day: &[&ScheduleEvent]) {
+    if !events_for_day.is_empty() {
+
+        // Overlay events are not ours, we don't travel to them
+        let own_events: Vec<&ScheduleEvent> = events_for_day.iter().filter(|event| event.overlay.is_none()).copied().collect();
+        let travel_info = travel_route(&own_events);
+        if travel_info.len() > 1 {
+            println!("           {}", format!("↳ ✈: {}", travel_info.join(" → ")).bright_blue().italic());
+        } else if travel_info.len() == 1 {
+            println!("           {}", format!("↳ ⌂: {}", travel_info.join(" → ")).bright_blue().italic());
+        }
+    }
+}
+
+fn print_event(event: &ScheduleEvent, timezone: &Tz) {
+    let start_time_local = event.start_time.with_timezone(timezone);
+    let end_time_local = event.end_time.with_timezone(timezone);
+    let duration = end_time_local - start_time_local; // Calculate duration in local time
+    let now = Utc::now().with_second(0).unwrap().with_nanosecond(0).unwrap().with_timezone(timezone);
+    let booked_str = if event.overlay.is_some() {
+        "◇".magenta()
+    } else if event.booked {
+        "✔".green()
+    } else {
+        if end_time_local < now { "✗".red() } else { "≈".blue() }
+    };
+    // Overlay events show their calendar instead of the project
+    let (project, task) = match &event.overlay {
+        Some(overlay) => (overlay.as_str(), event.summary.as_str()),
+        None => event.summary.split_once(':').unwrap_or(("", &event.summary)),
+    };
+    let project = if event.overlay.is_some() { project.bold().magenta() } else { project.bold().blue() };
+
+    let event_str = format!(
+        "{:02}:{:02} - {:02}:{:02} ({:02}:{:02}h) [{}] {}:{} ({})",
+        start_time_local.hour(),
+        start_time_local.minute(),
+        end_time_local.hour(),
+        end_time_local.minute(),
+        duration.num_hours(),
+        duration.num_minutes() % 60,
+        booked_str,
+        project,
+        task,
+        event.id.italic().dimmed(),
+    );
+
+    if start_time_local <= now && now < end_time_local {
+        println!("  {}", format!("› {}", event_str).yellow()); // Highlight current event
+
+        // // Percentage bar
+        // let elapsed_duration = now - start_time_local;
+        // let percentage = (elapsed_duration.num_minutes() as f64 / duration.num_minutes() as f64) * 100.0;
+        // let bar_width = 20; // Adjust as needed
+        // let filled_width = (percentage / 100.0 * bar_width as f64) as usize;
+        // let bar = format!(
+        //     "[{}{}] {:.1}%",
+        //     "#".repeat(filled_width).yellow(),
+        //     " ".repeat(bar_width - filled_width),
+        //     percentage
+        // );
+        // println!("    {}", bar);
+    } else {
+        println!("    {}", event_str);
+    }
+
+    if let Some(note) = &event.note {
+        println!("                               {}", format!("↳ ✎: {}", note).bright_blue());
+    }
+    if let Some(location) = &event.location {
+        println!("                               {}", format!("↳ ⌂: {}", location).bright_blue());
+    }
+    if !event.tags.is_empty() {
+        println!("                               {}", format!("↳ #: {}", event.tags.join(", ")).bright_blue());
+    }
+}
+
+fn format_duration(duration: Duration, human: bool) -> String {
+    if human {
+        let hours = duration.num_hours();
+        let minutes = duration.num_minutes() % 60;
+        format!("{:02}:{:02}h", hours, minutes)
+    } else {
+        let hours = duration.num_minutes() as f64 / 60.0;
+        format!("{:.2}", hours)
+    }
+}
+
+fn list_events(events: &[ScheduleEvent], overlay_events: &[ScheduleEvent], past_days: u32, future_days: u32, date_str: Option<String>, timezone: &Tz, summary: bool) {
+    if events.is_empty() {
+        println!("{}", "No events found".yellow());
+        return;
+    }
+
+    let now = if let Some(date_str) = date_str.clone() {
+        match NaiveDate::parse_from_str(&date_str, "%Y-%m-%d") {
+            Ok(date) => timezone.from_local_datetime(&date.and_hms_opt(0, 0, 0).unwrap()).unwrap(),
+            Err(_) => {
+                println!("{}", "Invalid date format. Using today.".yellow());
+                Utc::now().with_second(0).unwrap().with_nanosecond(0).unwrap().with_timezone(timezone)
+            }
+        }
+    } else {
+        Utc::now().with_second(0).unwrap().with_nanosecond(0).unwrap().with_timezone(timezone)
+    };
+
+    // let time_window_start = (now - Duration::days(days as i64)).with_time(NaiveTime::from_hms_opt(0, 0, 0).unwrap()).unwrap();
+    // let time_window_end = (now + Duration::days(days as i64)).with_time(NaiveTime::from_hms_opt(23, 59, 59).unwrap()).unwrap();
+
+    // let filtered_events: Vec<&ScheduleEvent> = events
+    //     .iter()
+    //     .filter(|event| event.start_time <= time_window_end && event.end_time >= time_window_start)
+    //     .collect();
+
+    // println!("Showing events within {} from {} in timezone: {}", format!("-{} and +{} days", past_days, future_days).yellow().bold(), now.date_naive(), timezone.name().green().bold());
+    let start_date = now - Duration::days(past_days as i64);
+    let end_date = now + Duration::days(future_days as i64);
+
+    println!(
+        "Showing events from {} to {} in timezone: {}",
+        format!("{}", start_date.format("%Y-%m-%d")).bright_cyan().bold(),
+        format!("{}", end_date.format("%Y-%m-%d")).bright_cyan().bold(),
+        timezone.name().bright_green().bold()
+    );
+    if summary {
+        let start_date = now - Duration::days(past_days as i64);
+        let end_date = now + Duration::days(future_days as i64);
+
+        let events_in_range: Vec<&ScheduleEvent> = events
+            .iter()
+            .filter(|event| {
+                event.start_time >= start_date.with_timezone(&Utc) && event.end_time <= end_date.with_timezone(&Utc)
+            })
+            .collect();
+        
+        let mut project_summary: BTreeMap<String, Duration> = BTreeMap::new();
+
+        for event in events_in_range.clone() {
+            let (project, _) = event.summary.split_once(':').unwrap_or(("", &event.summary));
+            let duration = event.end_time - event.start_time;
+            *project_summary.entry(project.to_string()).or_insert(Duration::zero()) += duration;
+        }
+
+        let travel_info = travel_route(&events_in_range);
+        if !travel_info.is_empty() {
+            println!("\n{} ({})", "Summary:".bright_yellow().bold(), travel_info.join(" → ").bright_blue().italic());
+        } else {
+            println!("\n{}", "Summary:".bright_yellow().bold());
+        }
+        for (project, duration) in project_summary {
+            println!("  {}: {}", project.bright_blue(), format_duration(duration, true));
+        }
+    }
+    println!("\n");
+    let events: Vec<ScheduleEvent> = events.iter().chain(overlay_events).cloned().sorted_by_key(|event| event.start_time).collect();
+    print_events_grouped_by_day(&events, timezone, past_days, date_str.clone(), true);
+    print_events_grouped_by_day(&events, timezone, future_days, date_str, false);
+    // print_events_grouped_by_day(&filtered_events, timezone);
+}
+
+#[derive(Serialize)]
+struct ListOutput<'a> {
+    timezone: &'static str,
+    from: String,
+    to: String,
+    events: Vec<EventOutput<'a>>,
+    unpublished: bool,
+}
+
+// Events of the listed days for json and tsv output
+fn print_list_output(events: &[ScheduleEvent], past_days: u32, future_days: u32, date_str: Option<&str>, timezone: &Tz, output: OutputFormat, unpublished: bool) -> Result<(), Error> {
+    let date = match date_str {
+        Some(date_str) => NaiveDate::parse_from_str(date_str, "%Y-%m-%d")
+            .map_err(|_| Error::new(ErrorKind::InvalidInput, "Invalid date format"))?,
+        None => Utc::now().with_timezone(timezone).date_naive(),
+    };
+    let from = date - Duration::days(past_days as i64);
+    let to = date + Duration::days(future_days as i64);
+    let listed: Vec<&ScheduleEvent> = events
+        .iter()
+        .filter(|event| (from..=to).contains(&event.start_time.with_timezone(timezone).date_naive()))
+        .sorted_by_key(|event| event.start_time)
+        .collect();
+
+    match output {
+        OutputFormat::Json => print_json(&ListOutput {
+            timezone: timezone.name(),
+            from: from.to_string(),
+            to: to.to_string(),
+            events: listed.iter().map(|event| EventOutput::new(event, timezone)).collect(),
+            unpublished,
+        }),
+        _ => {
+            print_events_tsv(&listed, timezone);
+            Ok(())
+        }
+    }
+}
+
+// Path of an event relative to the reported project
+fn report_task<'a>(summary: &'a str, project: Option<&str>) -> &'a str {
+    match project.and_then(|project| summary.strip_prefix(project)) {
+        Some("") => "(none)",
+        Some(rest) => rest.trim_start_matches(':'),
+        None => summary,
+    }
+}
+
+// Total time per project path, rolled up along every level of the path
+fn rollup_paths(events: &[&ScheduleEvent]) -> BTreeMap<String, Duration> {
+    let mut rollup: BTreeMap<String, Duration> = BTreeMap::new();
+    for event in events {
+        let segments: Vec<&str> = event.summary.split(':').collect();
+        for depth in 1..=segments.len() {
+            *rollup.entry(segments[..depth].join(":")).or_insert(Duration::zero()) += event.end_time - event.start_time;
+        }
+    }
+    rollup
+}
+
+// Working days and hours per location, travel days and home office days of booked events
+fn print_location_days(events: &[&ScheduleEvent], timezone: &Tz, home_locations: &[String]) {
+    let mut days: BTreeMap<NaiveDate, Vec<&ScheduleEvent>> = BTreeMap::new();
+    for event in events.iter().filter(|event| event.booked) {
+        days.entry(event.start_time.with_timezone(timezone).date_naive()).or_default().push(event);
+    }
+
+    let mut locations: BTreeMap<String, (usize, Duration)> = BTreeMap::new();
+    let mut travel_days: Vec<(NaiveDate, Vec<String>)> = Vec::new();
+    let (mut home_office_days, mut office_days, mut unknown_days) = (0, 0, 0);
+
+    for (date, day_events) in &days {
+        let mut day_events = day_events.clone();
+        day_events.sort_by_key(|event| event.start_time);
+
+        let mut day_locations: BTreeMap<String, Duration> = BTreeMap::new();
+        for event in &day_events {
+            if let Some(location) = &event.location {
+                *day_locations.entry(location.clone()).or_insert(Duration::zero()) += event.end_time - event.start_time;
+            }
+        }
+        for (location, duration) in day_locations {
+            let entry = locations.entry(location).or_insert((0, Duration::zero()));
+            entry.0 += 1;
+            entry.1 += duration;
+        }
+
+        let route = travel_route(&day_events);
+        match route.as_slice() {
+            [] => unknown_days += 1,
+            [location] if home_locations.contains(location) => home_office_days += 1,
+            [_] => office_days += 1,
+            _ => {
+                office_days += 1;
+                travel_days.push((*date, route));
+            }
+        }
+    }
+
+    println!("{}", "Working days per location (booked)".yellow().bold());
+    if locations.is_empty() {
+        println!("    {}", "No locations".italic());
+    }
+    for (location, (day_count, duration)) in &locations {
+        println!("  {:<30} {:>3} days {:>8}", location.bright_blue(), day_count, format_duration(*duration, false));
+    }
+    println!();
+
+    println!("{}", "Travel days".yellow().bold());
+    if travel_days.is_empty() {
+        println!("    {}", "No travel days".italic());
+    }
+    for (date, route) in &travel_days {
+        println!("  {} {}", date.format("%Y-%m-%d %a"), format!("✈: {}", route.join(" → ")).bright_blue().italic());
+    }
+    println!();
+
+    println!("  {}", format!("Home office days : {}", home_office_days).bright_green());
+    println!("  {}", format!("Office days      : {} ({} with travel)", office_days, travel_days.len()).bright_cyan());
+    if unknown_days > 0 {
+        println!("  {}", format!("Without location : {}", unknown_days).dimmed());
+    }
+    println!();
+}
+
+struct ReportOptions<'a> {
+    project: Option<&'a str>,
+    tag: Option<&'a str>,
+    by: ReportBy,
+    month: Option<u32>,
+    year: Option<i32>,
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+    target_time: Option<f64>,
+    home_locations: &'a [String],
+}
+
+// First and last day of a report or logbook, the given range or a month
+fn report_period(month: Option<u32>, year: Option<i32>, from: Option<NaiveDate>, to: Option<NaiveDate>, timezone: &Tz) -> Result<(NaiveDate, NaiveDate), Error> {
+    let today = Utc::now().with_timezone(timezone).date_naive();
+    if let Some(from) = from {
+        return Ok((from, to.unwrap_or(today)));
+    }
+    let first = NaiveDate::from_ymd_opt(year.unwrap_or(today.year()), month.unwrap_or(today.month()), 1)
+        .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "Invalid month"))?;
+    let last = first + chrono::Months::new(1) - Duration::days(1);
+    Ok((first, last))
+}
+
+// "11/2024" for a month, "2024-01-01 - 2024-12-31" otherwise
+fn period_label(from: NaiveDate, to: NaiveDate) -> String {
+    if from.day() == 1 && to == from + chrono::Months::new(1) - Duration::days(1) {
+        format!("{}/{}", from.month(), from.year())
+    } else {
+        format!("{} - {}", from, to)
+    }
+}
+
+fn billing_policy<'a>(billing: &'a HashMap<String, BillingPolicy>, summary: &str) -> Option<&'a BillingPolicy> {
+    let (project, _) = summary.split_once(':').unwrap_or((summary, ""));
+    billing.get(project).or_else(|| billing.get("*"))
+}
+
+// Billed duration of the booked events, rounded per project:task with the matching billing policy
+fn billed_time(events: &[&ScheduleEvent], billing: &HashMap<String, BillingPolicy>, timezone: &Tz) -> Duration {
+    let mut per_task: BTreeMap<&str, Vec<&ScheduleEvent>> = BTreeMap::new();
+    for event in events.iter().filter(|event| event.booked) {
+        per_task.entry(event.summary.as_str()).or_default().push(event);
+    }
+    per_task
+        .iter()
+        .map(|(summary, task_events)| match billing_policy(billing, summary) {
+            Some(policy) => policy.bill_events(task_events, timezone),
+            None => task_events.iter().map(|event| event.end_time - event.start_time).sum(),
+        })
+        .sum()
+}
+
+// Events of the reported month, below the reported project and with the reported tag
+fn report_events<'a>(events: &'a [ScheduleEvent], options: &ReportOptions, from: NaiveDate, to: NaiveDate, timezone: &Tz) -> Vec<&'a ScheduleEvent> {
+    events
+        .iter()
+        .filter(|event| {
+            options.project.is_none_or(|project| matches_path(&event.summary, project)) &&
+            options.tag.is_none_or(|tag| event.has_tag(tag)) &&
+            (from..=to).contains(&event.start_time.with_timezone(timezone).date_naive())
+        })
+        .collect()
+}
+
+// Events per task, tag or location, events with several tags are in several groups
+fn report_groups<'a>(events: &[&'a ScheduleEvent], options: &ReportOptions) -> BTreeMap<String, Vec<&'a ScheduleEvent>> {
+    let mut groups: BTreeMap<String, Vec<&ScheduleEvent>> = BTreeMap::new();
+    for event in events {
+        match options.by {
+            ReportBy::Task => {
+                let task = report_task(&event.summary, options.project);
+                groups.entry(task.to_string()).or_default().push(event);
+            }
+            ReportBy::Tag => {
+                if event.tags.is_empty() {
+                    groups.entry("(untagged)".to_string()).or_default().push(event);
+                }
+                for tag in &event.tags {
+                    groups.entry(format!("#{}", tag)).or_default().push(event);
+                }
+            }
+            ReportBy::Location => {
+                let location = event.location.clone().unwrap_or_else(|| "(no location)".to_string());
+                groups.entry(location).or_default().push(event);
+            }
+        }
+    }
+    groups
+}
+
+fn planned_and_booked(events: &[&ScheduleEvent]) -> (Duration, Duration) {
+    let mut planned_time = Duration::zero();
+    let mut booked_time = Duration::zero();
+    for event in events {
+        let duration = event.end_time - event.start_time;
+        if event.booked {
+            booked_time += duration;
+        } else {
+            planned_time += duration;
+        }
+    }
+    (planned_time, booked_time)
+}
+
+#[derive(Serialize)]
+struct ReportGroupOutput<'a> {
+    name: String,
+    minutes: i64,
+    billed_minutes: i64,
+    events: Vec<EventOutput<'a>>,
+}
+
+#[derive(Serialize)]
+struct RollupOutput {
+    path: String,
+    minutes: i64,
+}
+
+#[derive(Serialize)]
+struct ReportOutput<'a> {
+    project: Option<&'a str>,
+    tag: Option<String>,
+    by: &'static str,
+    year: Option<i32>,
+    month: Option<u32>,
+    from: String,
+    to: String,
+    timezone: &'static str,
+    groups: Vec<ReportGroupOutput<'a>>,
+    rollup: Vec<RollupOutput>,
+    total_minutes: i64,
+    planned_minutes: i64,
+    booked_minutes: i64,
+    billed_minutes: i64,
+    target_minutes: Option<i64>,
+}
+
+fn print_report_output(events: &[ScheduleEvent], options: &ReportOptions, timezone: &Tz, billing: &HashMap<String, BillingPolicy>, output: OutputFormat) -> Result<(), Error> {
+    let (from, to) = report_period(options.month, options.year, options.from, options.to, timezone)?;
+    let project_events = report_events(events, options, from, to, timezone);
+    let groups: Vec<ReportGroupOutput> = report_groups(&project_events, options)
+        .into_iter()
+        .map(|(name, group_events)| ReportGroupOutput {
+            name,
+            minutes: group_events.iter().map(|event| (event.end_time - event.start_time).num_minutes()).sum(),
+            billed_minutes: billed_time(&group_events, billing, timezone).num_minutes(),
+            events: group_events.iter().map(|event| EventOutput::new(event, timezone)).collect(),
+        })
+        .collect();
+
+    if output == OutputFormat::Tsv {
+        println!("group\tminutes\tbilled_minutes");
+        for group in &groups {
+            println!("{}\t{}\t{}", tsv_field(&group.name), group.minutes, group.billed_minutes);
+        }
+        return Ok(());
+    }
+
+    let (planned_time, booked_time) = planned_and_booked(&project_events);
+    print_json(&ReportOutput {
+        project: options.project,
+        tag: options.tag.map(normalize_tag),
+        by: match options.by {
+            ReportBy::Task => "task",
+            ReportBy::Tag => "tag",
+            ReportBy::Location => "location",
+        },
+        year: options.from.is_none().then_some(from.year()),
+        month: options.from.is_none().then_some(from.month()),
+        from: from.to_string(),
+        to: to.to_string(),
+        timezone: timezone.name(),
+        groups,
+        rollup: rollup_paths(&project_events).into_iter().map(|(path, duration)| RollupOutput { path, minutes: duration.num_minutes() }).collect(),
+        total_minutes: (planned_time + booked_time).num_minutes(),
+        planned_minutes: planned_time.num_minutes(),
+        booked_minutes: booked_time.num_minutes(),
+        billed_minutes: billed_time(&project_events, billing, timezone).num_minutes(),
+        target_minutes: options.target_time.map(|target| (target * 60.0) as i64),
+    })
+}
+
+fn generate_report(events: &[ScheduleEvent], options: &ReportOptions, timezone: &Tz, billing: &HashMap<String, BillingPolicy>) -> Result<(), Error> {
+    let (from, to) = report_period(options.month, options.year, options.from, options.to, timezone)?;
+    let period = period_label(from, to);
+    let project_name = options.project.unwrap_or("all projects");
+
+    println!("+------------------------");
+    println!("|{}", format!("Report for Project: {}", project_name).bright_blue().bold());
+    if let Some(tag) = options.tag {
+        println!("|{}", format!("Tag: #{}", normalize_tag(tag)).bright_blue());
+    }
+    if options.from.is_some() {
+        println!("|{}", format!("Period: {}", period).bright_yellow().bold());
+    } else {
+        println!("|{}", format!("Month/Year: {}", period).bright_yellow().bold());
+    }
+    println!("|{}", format!("Timezone: {}", timezone.name()).yellow());
+    println!("{}\n", "+---------------".dimmed()); // Use dimmed for separator
+    let project_events = report_events(events, options, from, to, timezone);
+
+    if project_events.is_empty() {
+        println!("{}", format!("No events found for {} in {}", project_name, period).yellow());
+        return Ok(());
+    }
+
+    let groups = report_groups(&project_events, options);
+    let (planned_time, booked_time) = planned_and_booked(&project_events);
+
+    let group_label = match options.by {
+        ReportBy::Task => "Task",
+        ReportBy::Tag => "Tag",
+        ReportBy::Location => "Location",
+    };
+    let show_billing = project_events.iter().any(|event| billing_policy(billing, &event.summary).is_some());
+    let sum_total_duration: Duration = project_events.iter().map(|event| event.end_time - event.start_time).sum();
+    let sum_billed_duration = billed_time(&project_events, billing, timezone);
+    for (group, group_events) in groups {
+        let total_duration: Duration = group_events.iter().map(|event| event.end_time - event.start_time).sum();
+
+        println!("{}", format!("{}: {}", group_label, group).green().bold());
+        if show_billing {
+            let billed_duration = billed_time(&group_events, billing, timezone);
+            println!("  {}", format!("Total Time: {} (billed: {})", format_duration(total_duration, false), format_duration(billed_duration, false)).bright_white());
+        } else {
+            println!("  {}", format!("Total Time: {}", format_duration(total_duration, false)).bright_white());
+        }
+
+        for event in group_events {
+            let duration = event.end_time - event.start_time;
+            let duration_str = match billing_policy(billing, &event.summary) {
+                Some(policy) if event.booked && policy.aggregate == BillingAggregate::Event => {
+                    format!("{} → {}", format_duration(duration, false), format_duration(policy.bill(duration), false))
+                }
+                _ => format_duration(duration, false),
+            };
+            let booked = if event.booked {
+                "[✔]".green()
+            } else {
+                if event.end_time.with_timezone(timezone) < Utc::now().with_second(0).unwrap().with_nanosecond(0).unwrap().with_timezone(timezone) {
+                    "[✗]".red()
+                } else {
+                    "[≈]".blue()
+                }
+            };
+            let task_str = match (options.by, options.project) {
+                (ReportBy::Task, _) => String::new(),
+                (_, project) => format!("{} ", report_task(&event.summary, project)),
+            };
+            println!(
+                "    {} - {} ({}) {} {}{} ({})",
+                event.start_time.with_timezone(timezone).format("%Y-%m-%d %H:%M"),
+                event.end_time.with_timezone(timezone).format("%H:%M"),
+                duration_str,
+                booked,
+                task_str.bright_blue(),
+                event.note.as_deref().unwrap_or_default(),
+                event.id.italic().dimmed(),
+            );
+         }
+         println!();
+    }
+
+    if options.by == ReportBy::Location {
+        print_location_days(&project_events, timezone, options.home_locations);
+    }
+
+    let rollup = rollup_paths(&project_events);
+    if rollup.keys().any(|path| path.split(':').count() > 2) || options.project.is_none() {
+        println!("{}", "Rollup".yellow().bold());
+        for (path, duration) in &rollup {
+            let depth = path.split(':').count();
+            let name = path.rsplit(':').next().unwrap_or(path);
+            let line = format!("{:indent$}{:<width$} {:>8}", "", name, format_duration(*duration, false), indent = depth * 2, width = 40usize.saturating_sub(depth * 2));
+            if depth == 1 {
+                println!("{}", line.bright_white().bold());
+            } else {
+                println!("{}", line);
+            }
+        }
+        println!();
+    }
+    println!("{}", "Summary".yellow().bold()); // Clearer section header
+    println!("  {}", format!("Total Time  : {}", format_duration(sum_total_duration, false)).bright_white().bold());
+    println!("  {}", format!("Planned Time: {}", format_duration(planned_time, false)).bright_blue());
+    println!("  {}", format!("Booked Time : {}", format_duration(booked_time, false)).bright_green());
+    if show_billing {
+        println!("  {}", format!("Billed Time : {}", format_duration(sum_billed_duration, false)).bright_magenta().bold());
+    }
+    if let Some(policy) = options.project.and_then(|project| billing_policy(billing, &format!("{}:", project))) {
+        let rounding = match policy.rounding {
+            BillingRounding::Up => "up",
+            BillingRounding::Nearest => "nearest",
+        };
+        let aggregate = match policy.aggregate {
+            BillingAggregate::Event => "event",
+            BillingAggregate::Day => "day and task",
+        };
+        println!("  {}", format!("Billing     : {} min, {} per {}", policy.increment, rounding, aggregate).dimmed());
+    }
+
+    if let Some(target) = options.target_time {
+        let target_duration = Duration::minutes((target * 60.0) as i64);
+        let diff = sum_total_duration - target_duration;
+
+        let diff_hours = diff.num_hours();
+        let diff_minutes = diff.num_minutes() % 60;
+
+        let diff_str = if diff > Duration::zero() {
+            format!("Overrun     : {diff_hours}h {diff_minutes}m").green()
+        } else {
+            format!("Underrun    : {}h {}m", -diff_hours, -diff_minutes).red()
+        };
+
+        let percentage_diff = if target_duration != Duration::zero() {
+            (diff.num_minutes() as f64 / target_duration.num_minutes() as f64) * 100.0
+        } else {
+            0.0 // Avoid division by zero if target is zero.
+        };
+
+        let target_hours = target_duration.num_hours();
+        let target_minutes = target_duration.num_minutes() % 60;
+        let target_str = format!("{target_hours}h {target_minutes}m");
+        // println!("  Time difference: {} of {} ({:.1}%)", diff_str, target_str.blue(), percentage_diff);
+        println!("  {}", format!("Target time : {}", target_str).bright_cyan());
+        println!("  {}", format!("{} ({:.1}%)", diff_str, percentage_diff).bright_white());
+    }
+    println!();
     Ok(())
 }
 
-fn print_events_grouped_by_day(events: &[ScheduleEvent], timezone: &Tz, days: u32, date_str: Option<String>, past: bool) {
-    let now = if let Some(date_str) = date_str {
-        match NaiveDate::parse_from_str(&date_str, "%Y-%m-%d") {
-            Ok(date) => timezone.from_local_datetime(&date.and_hms_opt(0, 0, 0).unwrap()).unwrap(),
-            Err(_) => {
-                println!("{}", "Invalid date format. Using today.".yellow());
-                Utc::now().with_second(0).unwrap().with_nanosecond(0).unwrap().with_timezone(timezone)
+// Returns the monday of the requested week, either given as ISO week (YYYY-Www) or as date
+fn parse_week(week_str: Option<&str>, timezone: &Tz) -> Result<NaiveDate, Error> {
+    let date = match week_str {
+        Some(week_str) => {
+            if let Some((year, week)) = week_str.split_once("-W") {
+                let year: i32 = year.parse().map_err(|_| Error::new(ErrorKind::InvalidInput, "Invalid week format"))?;
+                let week: u32 = week.parse().map_err(|_| Error::new(ErrorKind::InvalidInput, "Invalid week format"))?;
+                NaiveDate::from_isoywd_opt(year, week, Weekday::Mon).ok_or_else(|| Error::new(ErrorKind::InvalidInput, "Invalid week"))?
+            } else {
+                NaiveDate::parse_from_str(week_str, "%Y-%m-%d").map_err(|_| Error::new(ErrorKind::InvalidInput, "Invalid date format"))?
             }
         }
-    } else {
-        Utc::now().with_second(0).unwrap().with_nanosecond(0).unwrap().with_timezone(timezone)
+        None => Utc::now().with_timezone(timezone).date_naive(),
     };
-    let realnow = Utc::now().with_second(0).unwrap().with_nanosecond(0).unwrap().with_timezone(timezone);
-    let day_range = if past {
-        -(days as i64)..=-1
+    Ok(date - Duration::days(date.weekday().num_days_from_monday() as i64))
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
     } else {
-        0..=(days as i64)
+        value.to_string()
+    }
+}
+
+fn generate_timesheet(events: &[ScheduleEvent], monday: NaiveDate, timezone: &Tz, include_planned: bool) -> BTreeMap<String, [Duration; 7]> {
+    let sunday = monday + Duration::days(6);
+    let mut rows: BTreeMap<String, [Duration; 7]> = BTreeMap::new();
+
+    for event in events.iter().filter(|event| event.booked || include_planned) {
+        let date = event.start_time.with_timezone(timezone).date_naive();
+        if date < monday || date > sunday {
+            continue;
+        }
+        let day = date.weekday().num_days_from_monday() as usize;
+        rows.entry(event.summary.clone()).or_insert([Duration::zero(); 7])[day] += event.end_time - event.start_time;
+    }
+    rows
+}
+
+fn print_timesheet(rows: &BTreeMap<String, [Duration; 7]>, monday: NaiveDate, timezone: &Tz, human: bool) {
+    let week = monday.iso_week();
+    println!(
+        "Timesheet for week {} ({} - {}) in timezone: {}\n",
+        format!("{}-W{:02}", week.year(), week.week()).bright_cyan().bold(),
+        monday.format("%Y-%m-%d"),
+        (monday + Duration::days(6)).format("%Y-%m-%d"),
+        timezone.name().bright_green().bold()
+    );
+
+    if rows.is_empty() {
+        println!("{}", "No events found".yellow());
+        return;
+    }
+
+    let label_width = rows.keys().map(|summary| summary.chars().count()).max().unwrap_or(0).max("Project:Task".len());
+    let cell = |duration: Duration| if duration == Duration::zero() { "-".to_string() } else { format_duration(duration, human) };
+
+    let mut header = format!("{:<label_width$}", "Project:Task");
+    for day in 0..7 {
+        header.push_str(&format!(" {:>8}", (monday + Duration::days(day)).format("%a %d").to_string()));
+    }
+    header.push_str(&format!(" {:>8}", "Total"));
+    println!("{}", header.bright_blue().bold());
+
+    let mut day_totals = [Duration::zero(); 7];
+    for (summary, days) in rows {
+        let mut line = format!("{:<label_width$}", summary);
+        for (day, duration) in days.iter().enumerate() {
+            day_totals[day] += *duration;
+            line.push_str(&format!(" {:>8}", cell(*duration)));
+        }
+        let total: Duration = days.iter().copied().sum();
+        println!("{} {}", line, format!("{:>8}", cell(total)).bold());
+    }
+
+    let mut footer = format!("{:<label_width$}", "Total");
+    for duration in day_totals {
+        footer.push_str(&format!(" {:>8}", cell(duration)));
+    }
+    let week_total: Duration = day_totals.iter().copied().sum();
+    footer.push_str(&format!(" {:>8}", cell(week_total)));
+    println!("{}", footer.bright_white().bold());
+    println!();
+}
+
+fn agenda_events<'a>(events: &'a [ScheduleEvent], from: NaiveDate, to: NaiveDate, timezone: &Tz) -> Vec<&'a ScheduleEvent> {
+    events
+        .iter()
+        .filter(|event| (from..=to).contains(&event.start_time.with_timezone(timezone).date_naive()))
+        .sorted_by_key(|event| event.start_time)
+        .collect()
+}
+
+// Org tags only allow letters, digits, _, @, # and %
+fn org_tag(tag: &str) -> String {
+    tag.chars().map(|c| if c.is_alphanumeric() || "_@#%".contains(c) { c } else { '_' }).collect()
+}
+
+// Multi-line notes on one line, for formats with a line per event
+fn single_line(text: &str) -> String {
+    text.lines().map(str::trim).filter(|line| !line.is_empty()).join(" ")
+}
+
+fn export_org(events: &[&ScheduleEvent], timezone: &Tz) -> String {
+    let mut org = String::new();
+    for (date, day_events) in &events.iter().chunk_by(|event| event.start_time.with_timezone(timezone).date_naive()) {
+        org.push_str(&format!("* {}\n", date.format("%Y-%m-%d %a")));
+        for event in day_events {
+            let start = event.start_time.with_timezone(timezone);
+            let end = event.end_time.with_timezone(timezone);
+            let (project, _) = event.summary.split_once(':').unwrap_or((&event.summary, ""));
+            let tags = std::iter::once(org_tag(project)).chain(event.tags.iter().map(|tag| org_tag(tag))).join(":");
+            org.push_str(&format!("** {} {} :{}:\n", if event.booked { "DONE" } else { "TODO" }, event.summary, tags));
+            org.push_str(&format!("   SCHEDULED: <{}-{}>\n", start.format("%Y-%m-%d %a %H:%M"), end.format("%H:%M")));
+            org.push_str(&format!("   :PROPERTIES:\n   :ID:       {}\n", event.id));
+            if let Some(location) = &event.location {
+                org.push_str(&format!("   :LOCATION: {}\n", location));
+            }
+            org.push_str("   :END:\n");
+            if event.booked {
+                let duration = event.end_time - event.start_time;
+                org.push_str(&format!(
+                    "   :LOGBOOK:\n   CLOCK: [{}]--[{}] => {:2}:{:02}\n   :END:\n",
+                    start.format("%Y-%m-%d %a %H:%M"),
+                    end.format("%Y-%m-%d %a %H:%M"),
+                    duration.num_hours(),
+                    duration.num_minutes() % 60
+                ));
+            }
+            // Indented, a note line starting with * would be a heading otherwise
+            for line in event.note.iter().flat_map(|note| note.lines()) {
+                org.push_str(&format!("   {}\n", line));
+            }
+        }
+    }
+    org
+}
+
+fn export_markdown(events: &[&ScheduleEvent], timezone: &Tz) -> String {
+    let mut markdown = String::new();
+    for (date, day_events) in &events.iter().chunk_by(|event| event.start_time.with_timezone(timezone).date_naive()) {
+        markdown.push_str(&format!("## {}\n\n", date.format("%Y-%m-%d %a")));
+        for event in day_events {
+            let (project, task) = event.summary.split_once(':').unwrap_or((&event.summary, ""));
+            let mut line = format!(
+                "- [{}] {}-{} **{}**:{}",
+                if event.booked { "x" } else { " " },
+                event.start_time.with_timezone(timezone).format("%H:%M"),
+                event.end_time.with_timezone(timezone).format("%H:%M"),
+                project,
+                task
+            );
+            if let Some(location) = &event.location {
+                line.push_str(&format!(" @ {}", location));
+            }
+            if !event.tags.is_empty() {
+                line.push_str(&format!(" {}", format_tags(&event.tags)));
+            }
+            if let Some(note) = &event.note {
+                line.push_str(&format!(" - {}", single_line(note)));
+            }
+            markdown.push_str(&line);
+            markdown.push('\n');
+        }
+        markdown.push('\n');
+    }
+    markdown
+}
+
+// Events as todo.txt tasks, with the project as +project and the location as @context
+fn export_todotxt(events: &[&ScheduleEvent], timezone: &Tz) -> String {
+    let mut todotxt = String::new();
+    for event in events {
+        let start = event.start_time.with_timezone(timezone);
+        let (project, task) = event.summary.split_once(':').unwrap_or((&event.summary, ""));
+        let mut line = format!("{} +{}", if task.is_empty() { project } else { task }, project.replace(' ', "_"));
+        if let Some(location) = &event.location {
+            line.push_str(&format!(" @{}", location.replace(' ', "_")));
+        }
+        if let Some(note) = &event.note {
+            line.push_str(&format!(" {}", single_line(note)));
+        }
+        line.push_str(&format!(
+            " due:{} time:{}-{} id:{}",
+            start.format("%Y-%m-%d"),
+            start.format("%H:%M"),
+            event.end_time.with_timezone(timezone).format("%H:%M"),
+            event.id
+        ));
+        todotxt.push_str(&line);
+        todotxt.push('\n');
+    }
+    todotxt
+}
+
+// Minimal CSV reader, quoted fields may contain commas, quotes and line breaks
+fn parse_csv(content: &str) -> Vec<Vec<String>> {
+    let mut records = Vec::new();
+    let mut record = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = content.trim_start_matches('\u{feff}').chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, quoted) {
+            ('"', true) if chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            ('"', true) => quoted = false,
+            ('"', false) if field.is_empty() => quoted = true,
+            (',', false) => record.push(std::mem::take(&mut field)),
+            ('\n', false) => {
+                record.push(std::mem::take(&mut field));
+                records.push(std::mem::take(&mut record));
+            }
+            ('\r', false) => {}
+            (c, _) => field.push(c),
+        }
+    }
+    if !field.is_empty() || !record.is_empty() {
+        record.push(field);
+        records.push(record);
+    }
+    records.retain(|record| record.iter().any(|field| !field.is_empty()));
+    records
+}
+
+fn imported_event(start_time: DateTime<Utc>, end_time: DateTime<Utc>, summary: String, note: Option<String>, tags: Vec<String>) -> ScheduleEvent {
+    ScheduleEvent {
+        id: Uuid::new_v4().to_string(),
+        start_time,
+        end_time,
+        summary,
+        note,
+        location: None,
+        booked: true,
+        tags,
+        planned_ahead: Some(false),
+        overlay: None,
+    }
+}
+
+// project:task from tracker fields, missing parts become "imported"
+fn imported_summary(project: &str, task: &str) -> String {
+    let project = if project.trim().is_empty() { "imported" } else { project };
+    let task = if task.trim().is_empty() { "imported" } else { task };
+    parse_project_path(&format!("{}:{}", project.replace(':', " "), task)).unwrap_or_else(|_| "imported:imported".to_string())
+}
+
+// Dates and times as exported by Toggl and Clockify, depending on the user settings
+fn parse_tracker_datetime(date: &str, time: &str, timezone: &Tz) -> Option<DateTime<Utc>> {
+    let date = ["%Y-%m-%d", "%m/%d/%Y", "%d.%m.%Y"].iter().find_map(|format| NaiveDate::parse_from_str(date.trim(), format).ok())?;
+    let time = ["%H:%M:%S", "%H:%M", "%I:%M:%S %p", "%I:%M %p"].iter().find_map(|format| NaiveTime::parse_from_str(time.trim(), format).ok())?;
+    timezone.from_local_datetime(&date.and_time(time)).earliest().map(|datetime| datetime.with_timezone(&Utc))
+}
+
+// Whether an imported event is in the schedule already, adjacent imports may have been merged since
+fn is_imported(events: &[ScheduleEvent], imported: &ScheduleEvent) -> bool {
+    events.iter().any(|event| event.summary == imported.summary && event.start_time <= imported.start_time && event.end_time >= imported.end_time)
+}
+
+// Toggl Track detailed CSV and Clockify detailed CSV, columns are found by their header
+fn parse_tracker_csv(content: &str, timezone: &Tz) -> Result<Vec<ScheduleEvent>, Error> {
+    let records = parse_csv(content);
+    let Some((header, rows)) = records.split_first() else {
+        return Ok(Vec::new());
     };
+    let column = |name: &str| header.iter().position(|column| column.trim().eq_ignore_ascii_case(name));
+    let (Some(start_date), Some(start_time), Some(end_date), Some(end_time)) = (column("Start date"), column("Start time"), column("End date"), column("End time")) else {
+        return Err(Error::new(ErrorKind::InvalidData, "Missing start/end date or time columns in CSV file"));
+    };
+    let (project, client, task, description, tags, billable) = (column("Project"), column("Client"), column("Task"), column("Description"), column("Tags"), column("Billable"));
+
+    let mut events = Vec::new();
+    for (line, row) in rows.iter().enumerate() {
+        let field = |index: Option<usize>| index.and_then(|index| row.get(index)).map(|field| field.trim()).unwrap_or_default();
+        let start = parse_tracker_datetime(field(Some(start_date)), field(Some(start_time)), timezone);
+        let end = parse_tracker_datetime(field(Some(end_date)), field(Some(end_time)), timezone);
+        let (Some(start), Some(end)) = (start, end) else {
+            return Err(Error::new(ErrorKind::InvalidData, format!("Invalid start or end in CSV line {}", line + 2)));
+        };
+        // The description is the task if there is no task, otherwise the note
+        let project_name = if field(project).is_empty() { field(client) } else { field(project) };
+        let (task_name, note) = if field(task).is_empty() { (field(description), None) } else { (field(task), Some(field(description))) };
+        let mut event_tags: Vec<String> = field(tags).split(',').map(|tag| tag.to_string()).collect();
+        if ["yes", "true", "1"].contains(&field(billable).to_lowercase().as_str()) {
+            event_tags.push("billable".to_string());
+        }
+        let note = note.filter(|note| !note.is_empty()).map(|note| note.to_string());
+        events.push(imported_event(start, end, imported_summary(project_name, task_name), note, collect_tags(&[], &event_tags, None)));
+    }
+    Ok(events)
+}
 
-    for day_offset in day_range {
-        let current_date = now.date_naive() + Duration::days(day_offset as i64);
-        let date_str = current_date.format("%Y-%m-%d - %a").to_string();
-        let date_str = if current_date == realnow.date_naive() {
-            date_str.bright_yellow().bold().to_string()
-        } else {
-            date_str.bright_blue().bold().to_string()
+// Splits Timewarrior tags, tags with spaces are quoted
+fn split_timewarrior_tags(tags: &str) -> Vec<String> {
+    let mut result = Vec::new();
+    let mut current = String::new();
+    let mut quoted = false;
+    let mut chars = tags.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if quoted => current.extend(chars.next()),
+            '"' => quoted = !quoted,
+            c if c.is_whitespace() && !quoted => {
+                if !current.is_empty() {
+                    result.push(std::mem::take(&mut current));
+                }
+            }
+            c => current.push(c),
+        }
+    }
+    if !current.is_empty() {
+        result.push(current);
+    }
+    result
+}
+
+// A tag with a colon is the project:task, otherwise the first two tags are project and task
+fn timewarrior_event(start: DateTime<Utc>, end: DateTime<Utc>, tags: &[String], annotation: Option<String>) -> ScheduleEvent {
+    let (summary, other_tags) = match tags.iter().position(|tag| parse_project_path(tag).is_ok()) {
+        Some(index) => (parse_project_path(&tags[index]).unwrap(), tags.iter().enumerate().filter(|(i, _)| *i != index).map(|(_, tag)| tag.clone()).collect()),
+        None => (
+            imported_summary(tags.first().map_or("", |tag| tag.as_str()), tags.get(1).map_or("", |tag| tag.as_str())),
+            tags.iter().skip(2).cloned().collect::<Vec<_>>(),
+        ),
+    };
+    imported_event(start, end, summary, annotation.filter(|annotation| !annotation.is_empty()), collect_tags(&[], &other_tags, None))
+}
+
+// Timewarrior JSON from `timew export` or the data files in ~/.timewarrior/data, open intervals are skipped
+fn parse_timewarrior(content: &str) -> Result<Vec<ScheduleEvent>, Error> {
+    let parse_time = |value: &str| NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%SZ").ok().map(|naive| naive.and_utc());
+    let mut events = Vec::new();
+
+    if content.trim_start().starts_with('[') {
+        let intervals: Vec<serde_json::Value> = serde_json::from_str(content)
+            .map_err(|e| Error::new(ErrorKind::InvalidData, format!("Invalid Timewarrior JSON: {}", e)))?;
+        for interval in intervals {
+            let start = interval["start"].as_str().and_then(parse_time);
+            let end = interval["end"].as_str().and_then(parse_time);
+            let tags: Vec<String> = interval["tags"].as_array().map(|tags| tags.iter().filter_map(|tag| tag.as_str().map(|tag| tag.to_string())).collect()).unwrap_or_default();
+            if let (Some(start), Some(end)) = (start, end) {
+                events.push(timewarrior_event(start, end, &tags, interval["annotation"].as_str().map(|annotation| annotation.to_string())));
+            }
+        }
+        return Ok(events);
+    }
+
+    for line in content.lines().filter(|line| line.starts_with("inc ")) {
+        let (interval, rest) = line[4..].split_once(" # ").unwrap_or((&line[4..], ""));
+        let (tags, annotation) = match rest.rsplit_once(" # ") {
+            Some((tags, annotation)) => (tags, Some(annotation.trim().trim_matches('"').to_string())),
+            None => (rest, None),
         };
-        println!("{}", date_str);
+        if let Some((start, end)) = interval.trim().split_once(" - ") {
+            if let (Some(start), Some(end)) = (parse_time(start.trim()), parse_time(end.trim())) {
+                events.push(timewarrior_event(start, end, &split_timewarrior_tags(tags), annotation));
+            }
+        }
+    }
+    Ok(events)
+}
 
-        let events_for_day: Vec<&ScheduleEvent> = events
-            .iter()
-            .filter(|event| {
-                let event_start_date = event.start_time.with_timezone(timezone).date_naive();
-                 event_start_date == current_date
-            })
-            .collect();
+fn export_timewarrior(events: &[&ScheduleEvent]) -> Result<String, Error> {
+    let intervals: Vec<serde_json::Value> = events
+        .iter()
+        .enumerate()
+        .map(|(index, event)| {
+            let mut interval = serde_json::json!({
+                "id": index + 1,
+                "start": event.start_time.format("%Y%m%dT%H%M%SZ").to_string(),
+                "end": event.end_time.format("%Y%m%dT%H%M%SZ").to_string(),
+                "tags": std::iter::once(&event.summary).chain(&event.tags).collect::<Vec<_>>(),
+            });
+            if let Some(note) = &event.note {
+                interval["annotation"] = serde_json::Value::from(note.clone());
+            }
+            interval
+        })
+        .collect();
+    Ok(serde_json::to_string_pretty(&intervals)? + "\n")
+}
 
-        print_day_travel(&events_for_day);
-        if events_for_day.is_empty() {
-            println!("    {}", "No events".italic());
-        } else {
-            let mut last_end_time: Option<DateTime<Tz>> = None;
-            for event in events_for_day {
-                let start_time_local = event.start_time.with_timezone(timezone);
+// Booked events as timeclock entries in local time, with project:task as the account and tags as hledger tags.
+// A `;` starts the comment, so it's written as `,` in notes
+fn export_timeclock(events: &[&ScheduleEvent], timezone: &Tz) -> String {
+    let mut timeclock = String::new();
+    for event in events {
+        let mut line = format!("i {} {}", event.start_time.with_timezone(timezone).format("%Y/%m/%d %H:%M:%S"), event.summary);
+        if let Some(note) = &event.note {
+            line.push_str(&format!("  {}", note.replace('\n', " ").replace(';', ",")));
+        }
+        if !event.tags.is_empty() {
+            line.push_str(&format!("  ; {}", event.tags.iter().map(|tag| format!("{}:", tag)).join(", ")));
+        }
+        timeclock.push_str(&line);
+        timeclock.push('\n');
+        timeclock.push_str(&format!("o {}\n", event.end_time.with_timezone(timezone).format("%Y/%m/%d %H:%M:%S")));
+    }
+    timeclock
+}
 
-                if let Some(last_et) = last_end_time {
-                    let free_time = start_time_local - last_et;
-                    if free_time > Duration::zero() {
-                        let now = Utc::now().with_second(0).unwrap().with_nanosecond(0).unwrap().with_timezone(timezone);
-                        if last_et <= now && now < start_time_local {
-                            let from_last_event = now - last_et;
-                            let til_next_event = start_time_local - now;
-                            println!("       {}                  {}",
-                                format_duration(from_last_event, true).bright_yellow().italic(),
-                                format!("⋮").bright_yellow());
-                            println!("  {}                        {} {}",
-                                "› ...".bright_yellow(),
-                                format_duration(free_time, true).bright_yellow(),
-                                "free".bright_yellow());
-                            println!("       {}                  {}",
-                                format_duration(til_next_event, true).bright_yellow().italic(),
-                                format!("⋮").bright_yellow());
-                        } else {
-                            println!("                               {}", format!("⋮").bright_green());
-                            println!("                               {} {}", format_duration(free_time, true).bright_green(), "free".bright_green());
-                            println!("                               {}", format!("⋮").bright_green());
-                        }
-                    }
+fn parse_timeclock(content: &str, timezone: &Tz) -> Result<Vec<ScheduleEvent>, Error> {
+    let parse_time = |date: &str, time: &str| {
+        let date = ["%Y/%m/%d", "%Y-%m-%d", "%Y.%m.%d"].iter().find_map(|format| NaiveDate::parse_from_str(date, format).ok())?;
+        let time = ["%H:%M:%S", "%H:%M"].iter().find_map(|format| NaiveTime::parse_from_str(time, format).ok())?;
+        timezone.from_local_datetime(&date.and_time(time)).earliest().map(|datetime| datetime.with_timezone(&Utc))
+    };
+    let invalid = |line: usize, message: &str| Error::new(ErrorKind::InvalidData, format!("{} in timeclock line {}", message, line + 1));
+
+    let mut events = Vec::new();
+    let mut clocked_in: Option<ScheduleEvent> = None;
+    for (index, line) in content.lines().enumerate() {
+        let mut parts = line.splitn(4, ' ');
+        let (Some(code), Some(date), Some(time)) = (parts.next(), parts.next(), parts.next()) else { continue };
+        let rest = parts.next().unwrap_or_default();
+        match code {
+            "i" => {
+                if clocked_in.is_some() {
+                    return Err(invalid(index, "Clock-in without clock-out"));
                 }
+                let start = parse_time(date, time).ok_or_else(|| invalid(index, "Invalid date"))?;
+                let (entry, comment) = rest.split_once(';').unwrap_or((rest, ""));
+                let (account, description) = entry.split_once("  ").unwrap_or((entry, ""));
+                let summary = parse_project_path(account).unwrap_or_else(|_| imported_summary(account, ""));
+                let note = Some(description.trim().to_string()).filter(|note| !note.is_empty());
+                let tags: Vec<String> = comment.split(',').filter_map(|tag| tag.trim().strip_suffix(':')).map(|tag| tag.to_string()).collect();
+                clocked_in = Some(imported_event(start, start, summary, note, collect_tags(&[], &tags, None)));
+            }
+            "o" | "O" => {
+                let end = parse_time(date, time).ok_or_else(|| invalid(index, "Invalid date"))?;
+                let mut event = clocked_in.take().ok_or_else(|| invalid(index, "Clock-out without clock-in"))?;
+                event.end_time = end;
+                events.push(event);
+            }
+            _ => {}
+        }
+    }
+    Ok(events)
+}
 
-                print_event(event, timezone);
-                last_end_time = Some(event.end_time.with_timezone(timezone));
-                // print_event(event, timezone);
+fn format_hms(duration: Duration) -> String {
+    format!("{:02}:{:02}:{:02}", duration.num_hours(), duration.num_minutes() % 60, duration.num_seconds() % 60)
+}
+
+// Events as Toggl Track or Clockify detailed CSV
+fn export_tracker_csv(events: &[&ScheduleEvent], format: ExportFormat, timezone: &Tz) -> String {
+    let mut csv = match format {
+        ExportFormat::Clockify => String::from("Project,Client,Description,Task,User,Group,Email,Tags,Billable,Start Date,Start Time,End Date,End Time,Duration (h),Duration (decimal)\n"),
+        _ => String::from("User,Email,Client,Project,Task,Description,Billable,Start date,Start time,End date,End time,Duration,Tags\n"),
+    };
+    for event in events {
+        let (project, task) = event.summary.split_once(':').unwrap_or((&event.summary, ""));
+        let start = event.start_time.with_timezone(timezone);
+        let end = event.end_time.with_timezone(timezone);
+        let duration = event.end_time - event.start_time;
+        let note = csv_field(event.note.as_deref().unwrap_or_default());
+        let billable = if event.has_tag("billable") { "Yes" } else { "No" };
+        let tags = csv_field(&event.tags.join(", "));
+        let (start_date, start_time, end_date, end_time) = (start.format("%Y-%m-%d"), start.format("%H:%M:%S"), end.format("%Y-%m-%d"), end.format("%H:%M:%S"));
+        csv.push_str(&match format {
+            ExportFormat::Clockify => format!(
+                "{},,{},{},,,,{},{},{},{},{},{},{},{}\n",
+                csv_field(project), note, csv_field(task), tags, billable, start_date, start_time, end_date, end_time, format_hms(duration), format_duration(duration, false)
+            ),
+            _ => format!(
+                ",,,{},{},{},{},{},{},{},{},{},{}\n",
+                csv_field(project), csv_field(task), note, billable, start_date, start_time, end_date, end_time, format_hms(duration), tags
+            ),
+        });
+    }
+    csv
+}
+
+fn export_timesheet_csv(file_path: &PathBuf, rows: &BTreeMap<String, [Duration; 7]>, monday: NaiveDate, human: bool) -> Result<(), Error> {
+    let mut csv = String::from("project,task");
+    for day in 0..7 {
+        csv.push_str(&format!(",{}", (monday + Duration::days(day)).format("%Y-%m-%d")));
+    }
+    csv.push_str(",total\n");
+
+    let mut day_totals = [Duration::zero(); 7];
+    for (summary, days) in rows {
+        let (project, task) = summary.split_once(':').unwrap_or(("", summary));
+        csv.push_str(&format!("{},{}", csv_field(project), csv_field(task)));
+        for (day, duration) in days.iter().enumerate() {
+            day_totals[day] += *duration;
+            csv.push_str(&format!(",{}", format_duration(*duration, human)));
+        }
+        csv.push_str(&format!(",{}\n", format_duration(days.iter().copied().sum(), human)));
+    }
+
+    csv.push_str("Total,");
+    for duration in day_totals {
+        csv.push_str(&format!(",{}", format_duration(duration, human)));
+    }
+    csv.push_str(&format!(",{}\n", format_duration(day_totals.iter().copied().sum(), human)));
+
+    std::fs::write(file_path, csv)?;
+    println!("Timesheet exported to {}", file_path.display());
+    Ok(())
+}
+
+fn format_signed_duration(duration: Duration, human: bool) -> String {
+    let sign = if duration < Duration::zero() { "-" } else { "+" };
+    format!("{}{}", sign, format_duration(duration.abs(), human))
+}
+
+fn print_balance(events: &[ScheduleEvent], working_time: &WorkingTime, until: NaiveDate, weeks: u32, timezone: &Tz, human: bool) -> Result<(), Error> {
+    let absences = working_time.absence_ranges()?;
+    let start_date = working_time.start_date;
+
+    println!(
+        "Working time balance from {} to {} in timezone: {}\n",
+        start_date.format("%Y-%m-%d").to_string().bright_cyan().bold(),
+        until.format("%Y-%m-%d").to_string().bright_cyan().bold(),
+        timezone.name().bright_green().bold()
+    );
+    if until < start_date {
+        println!("{}", "Date is before the start of the working time account".yellow());
+        return Ok(());
+    }
+
+    let mut booked_per_day: HashMap<NaiveDate, Duration> = HashMap::new();
+    for event in events.iter().filter(|event| event.booked) {
+        let date = event.start_time.with_timezone(timezone).date_naive();
+        *booked_per_day.entry(date).or_insert(Duration::zero()) += event.end_time - event.start_time;
+    }
+
+    let detail_from = until - Duration::days(until.weekday().num_days_from_monday() as i64) - Duration::weeks(weeks.saturating_sub(1) as i64);
+    let mut balance = Duration::zero();
+    let mut total_target = Duration::zero();
+    let mut total_booked = Duration::zero();
+    let mut week_target = Duration::zero();
+    let mut week_booked = Duration::zero();
+
+    let mut date = start_date;
+    while date <= until {
+        let free_day = if working_time.holidays.contains(&date) {
+            Some("holiday")
+        } else if absences.iter().any(|(from, to)| *from <= date && date <= *to) {
+            Some("absence")
+        } else {
+            None
+        };
+        let target = if free_day.is_some() { Duration::zero() } else { working_time.hours.target(date.weekday()) };
+        let booked = booked_per_day.get(&date).copied().unwrap_or(Duration::zero());
+        let diff = booked - target;
+
+        balance += diff;
+        total_target += target;
+        total_booked += booked;
+        week_target += target;
+        week_booked += booked;
+
+        if date >= detail_from {
+            if date.weekday() == Weekday::Mon || date == detail_from {
+                let week = date.iso_week();
+                println!("{}", format!("Week {}-W{:02}", week.year(), week.week()).bright_blue().bold());
             }
+            let diff_str = if diff < Duration::zero() {
+                format_signed_duration(diff, human).red()
+            } else {
+                format_signed_duration(diff, human).green()
+            };
+            println!(
+                "    {}  target {}  booked {}  {}  balance {} {}",
+                date.format("%Y-%m-%d %a"),
+                format_duration(target, human),
+                format_duration(booked, human),
+                diff_str,
+                format_signed_duration(balance, human).bold(),
+                free_day.unwrap_or_default().italic().dimmed(),
+            );
         }
-        println!();
+        if date >= detail_from && (date.weekday() == Weekday::Sun || date == until) {
+            println!(
+                "  {}\n",
+                format!(
+                    "Week total: target {}  booked {}  {}",
+                    format_duration(week_target, human),
+                    format_duration(week_booked, human),
+                    format_signed_duration(week_booked - week_target, human)
+                ).bright_white()
+            );
+        }
+        if date.weekday() == Weekday::Sun {
+            week_target = Duration::zero();
+            week_booked = Duration::zero();
+        }
+        date += Duration::days(1);
+    }
+
+    println!("{}", "Summary".yellow().bold());
+    println!("  {}", format!("Target Time : {}", format_duration(total_target, human)).bright_cyan());
+    println!("  {}", format!("Booked Time : {}", format_duration(total_booked, human)).bright_green());
+    let balance_str = format!("Balance     : {}", format_signed_duration(balance, human));
+    if balance < Duration::zero() {
+        println!("  {}", balance_str.red().bold());
+    } else {
+        println!("  {}", balance_str.green().bold());
     }
+    println!();
+    Ok(())
 }
 
-// Print travel information
-fn print_day_travel(events_for_day: &[&ScheduleEvent]) {
-    if !events_for_day.is_empty() {
+struct Trip {
+    time: DateTime<Tz>,
+    from: String,
+    to: String,
+    purpose: String,
+    km: Option<f64>,
+}
 
-        let mut travel_info: Vec<String> = Vec::new();
-        let mut last_location: Option<String> = None;
-        for event in events_for_day {
-            if let Some(location) = &event.location {
-                if last_location.as_ref() != Some(location) {
-                    if let Some(last_loc) = last_location {
-                        travel_info.push(last_loc);
-                    }
-                    last_location = Some(location.clone());
-                }
-            }
+// Trips between the locations of booked events, the purpose is the project at the destination
+fn mileage_trips(events: &[ScheduleEvent], config: &Config, timezone: &Tz, from: NaiveDate, to: NaiveDate, commute: bool) -> Vec<Trip> {
+    let mut days: BTreeMap<NaiveDate, Vec<&ScheduleEvent>> = BTreeMap::new();
+    for event in events.iter().filter(|event| event.booked && event.location.is_some()) {
+        let date = event.start_time.with_timezone(timezone).date_naive();
+        if (from..=to).contains(&date) {
+            days.entry(date).or_default().push(event);
         }
-        if let Some(last_loc) = last_location {
-            travel_info.push(last_loc);
+    }
+
+    let home = config.home_locations.first();
+    let mut trips = Vec::new();
+    for day_events in days.values_mut() {
+        day_events.sort_by_key(|event| event.start_time);
+        let mut last: Option<&ScheduleEvent> = None;
+        for event in day_events.iter() {
+            let location = event.location.clone().unwrap_or_default();
+            let from = match last {
+                Some(last_event) => last_event.location.clone().unwrap_or_default(),
+                None if commute => match home {
+                    Some(home) => home.clone(),
+                    None => location.clone(),
+                },
+                None => location.clone(),
+            };
+            if from != location {
+                trips.push(Trip {
+                    time: last.map(|last_event| last_event.end_time).unwrap_or(event.start_time).with_timezone(timezone),
+                    km: config.distance(&from, &location),
+                    from,
+                    to: location,
+                    purpose: event.summary.split(':').next().unwrap_or_default().to_string(),
+                });
+            }
+            last = Some(event);
         }
-        if travel_info.len() > 1 {
-            println!("           {}", format!("↳ ✈: {}", travel_info.join(" → ")).bright_blue().italic());
-        } else if travel_info.len() == 1 {
-            println!("           {}", format!("↳ ⌂: {}", travel_info.join(" → ")).bright_blue().italic());
+        if let (true, Some(home), Some(last_event)) = (commute, home, last) {
+            let location = last_event.location.clone().unwrap_or_default();
+            if &location != home {
+                trips.push(Trip {
+                    time: last_event.end_time.with_timezone(timezone),
+                    km: config.distance(&location, home),
+                    from: location,
+                    to: home.clone(),
+                    purpose: "Return".to_string(),
+                });
+            }
         }
     }
+    trips
 }
 
-fn print_event(event: &ScheduleEvent, timezone: &Tz) {
-    let start_time_local = event.start_time.with_timezone(timezone);
-    let end_time_local = event.end_time.with_timezone(timezone);
-    let duration = end_time_local - start_time_local; // Calculate duration in local time
-    let now = Utc::now().with_second(0).unwrap().with_nanosecond(0).unwrap().with_timezone(timezone);
-    let booked_str = if event.booked {
-        "✔".green()
-    } else {
-        if end_time_local < now { "✗".red() } else { "≈".blue() }
-    };
-    let (project, task) = event.summary.split_once(':').unwrap_or(("", &event.summary));
-
-    let event_str = format!(
-        "{:02}:{:02} - {:02}:{:02} ({:02}:{:02}h) [{}] {}:{} ({})",
-        start_time_local.hour(),
-        start_time_local.minute(),
-        end_time_local.hour(),
-        end_time_local.minute(),
-        duration.num_hours(),
-        duration.num_minutes() % 60,
-        booked_str,
-        project.bold().blue(),
-        task,
-        event.id.italic().dimmed(),
-    );
-
-    if start_time_local <= now && now < end_time_local {
-        println!("  {}", format!("› {}", event_str).yellow()); // Highlight current event
+fn print_mileage(trips: &[Trip], config: &Config, from: NaiveDate, to: NaiveDate) {
+    let period = period_label(from, to);
+    println!("+------------------------");
+    println!("|{}", "Mileage Logbook".bright_blue().bold());
+    println!("|{}", format!("Period: {}", period).bright_yellow().bold());
+    println!("{}\n", "+---------------".dimmed());
 
-        // // Percentage bar
-        // let elapsed_duration = now - start_time_local;
-        // let percentage = (elapsed_duration.num_minutes() as f64 / duration.num_minutes() as f64) * 100.0;
-        // let bar_width = 20; // Adjust as needed
-        // let filled_width = (percentage / 100.0 * bar_width as f64) as usize;
-        // let bar = format!(
-        //     "[{}{}] {:.1}%",
-        //     "#".repeat(filled_width).yellow(),
-        //     " ".repeat(bar_width - filled_width),
-        //     percentage
-        // );
-        // println!("    {}", bar);
-    } else {
-        println!("    {}", event_str);
+    if trips.is_empty() {
+        println!("{}", format!("No trips found in {}", period).yellow());
+        return;
     }
 
-    if let Some(note) = &event.note {
-        println!("                               {}", format!("↳ ✎: {}", note).bright_blue());
+    let mut total_km = 0.0;
+    let mut unknown = 0;
+    for trip in trips {
+        let km = match trip.km {
+            Some(km) => {
+                total_km += km;
+                format!("{:>7.1} km", km)
+            }
+            None => {
+                unknown += 1;
+                format!("{:>10}", "? km").red().to_string()
+            }
+        };
+        println!(
+            "    {} {} → {} {} {}",
+            trip.time.format("%Y-%m-%d %H:%M"),
+            config.address(&trip.from),
+            config.address(&trip.to),
+            km,
+            format!("({})", trip.purpose).bright_blue(),
+        );
     }
-    if let Some(location) = &event.location {
-        println!("                               {}", format!("↳ ⌂: {}", location).bright_blue());
+    println!();
+    println!("{}", "Summary".yellow().bold());
+    println!("  {}", format!("Trips       : {}", trips.len()).bright_white());
+    println!("  {}", format!("Distance    : {:.1} km", total_km).bright_white().bold());
+    if unknown > 0 {
+        println!("  {}", format!("{} trips without configured distance", unknown).red());
     }
+    println!();
 }
 
-fn format_duration(duration: Duration, human: bool) -> String {
-    if human {
-        let hours = duration.num_hours();
-        let minutes = duration.num_minutes() % 60;
-        format!("{:02}:{:02}h", hours, minutes)
-    } else {
-        let hours = duration.num_minutes() as f64 / 60.0;
-        format!("{:.2}", hours)
+fn export_mileage_csv(file_path: &PathBuf, trips: &[Trip], config: &Config) -> Result<(), Error> {
+    let mut csv = String::from("date,time,from,to,purpose,km\n");
+    for trip in trips {
+        csv.push_str(&format!(
+            "{},{},{},{},{},{}\n",
+            trip.time.format("%Y-%m-%d"),
+            trip.time.format("%H:%M"),
+            csv_field(config.address(&trip.from)),
+            csv_field(config.address(&trip.to)),
+            csv_field(&trip.purpose),
+            trip.km.map(|km| format!("{:.1}", km)).unwrap_or_default(),
+        ));
     }
+    std::fs::write(file_path, csv)?;
+    println!("Mileage logbook exported to {}", file_path.display());
+    Ok(())
 }
 
-fn list_events(events: &[ScheduleEvent],past_days: u32, future_days: u32, date_str: Option<String>, timezone: &Tz, summary: bool) {
-    if events.is_empty() {
-        println!("{}", "No events found".yellow());
-        return;
-    }
+#[derive(Default)]
+struct DayStats {
+    events: usize,
+    total: Duration,
+    longest_block: Duration,
+    short_blocks: Duration,
+    context_switches: usize,
+    gaps: usize,
+    free: Duration,
+}
 
-    let now = if let Some(date_str) = date_str.clone() {
-        match NaiveDate::parse_from_str(&date_str, "%Y-%m-%d") {
-            Ok(date) => timezone.from_local_datetime(&date.and_hms_opt(0, 0, 0).unwrap()).unwrap(),
-            Err(_) => {
-                println!("{}", "Invalid date format. Using today.".yellow());
-                Utc::now().with_second(0).unwrap().with_nanosecond(0).unwrap().with_timezone(timezone)
-            }
-        }
-    } else {
-        Utc::now().with_second(0).unwrap().with_nanosecond(0).unwrap().with_timezone(timezone)
-    };
+const SHORT_BLOCK_MINUTES: i64 = 30;
 
-    // let time_window_start = (now - Duration::days(days as i64)).with_time(NaiveTime::from_hms_opt(0, 0, 0).unwrap()).unwrap();
-    // let time_window_end = (now + Duration::days(days as i64)).with_time(NaiveTime::from_hms_opt(23, 59, 59).unwrap()).unwrap();
+// Blocks are adjacent events of the same project without a gap in between
+fn day_stats(day_events: &[&ScheduleEvent]) -> DayStats {
+    let mut stats = DayStats { events: day_events.len(), ..Default::default() };
+    let mut blocks: Vec<Duration> = Vec::new();
+    let mut previous: Option<&ScheduleEvent> = None;
 
-    // let filtered_events: Vec<&ScheduleEvent> = events
-    //     .iter()
-    //     .filter(|event| event.start_time <= time_window_end && event.end_time >= time_window_start)
-    //     .collect();
+    for event in day_events {
+        let duration = event.end_time - event.start_time;
+        stats.total += duration;
+        let project = event.summary.split(':').next();
 
-    // println!("Showing events within {} from {} in timezone: {}", format!("-{} and +{} days", past_days, future_days).yellow().bold(), now.date_naive(), timezone.name().green().bold());
-    let start_date = now - Duration::days(past_days as i64);
-    let end_date = now + Duration::days(future_days as i64);
+        match previous {
+            Some(prev) if prev.end_time >= event.start_time && prev.summary.split(':').next() == project => {
+                if let Some(block) = blocks.last_mut() {
+                    *block += duration;
+                }
+            }
+            Some(prev) => {
+                if prev.summary.split(':').next() != project {
+                    stats.context_switches += 1;
+                }
+                if event.start_time > prev.end_time {
+                    stats.gaps += 1;
+                    stats.free += event.start_time - prev.end_time;
+                }
+                blocks.push(duration);
+            }
+            None => blocks.push(duration),
+        }
+        previous = Some(event);
+    }
 
+    stats.longest_block = blocks.iter().copied().max().unwrap_or(Duration::zero());
+    stats.short_blocks = blocks.iter().filter(|block| block.num_minutes() < SHORT_BLOCK_MINUTES).copied().sum();
+    stats
+}
+
+fn print_stats(events: &[ScheduleEvent], until: NaiveDate, past_days: u32, booked_only: bool, timezone: &Tz) {
+    let from = until - Duration::days(past_days as i64);
     println!(
-        "Showing events from {} to {} in timezone: {}",
-        format!("{}", start_date.format("%Y-%m-%d")).bright_cyan().bold(),
-        format!("{}", end_date.format("%Y-%m-%d")).bright_cyan().bold(),
+        "Focus statistics from {} to {} in timezone: {}\n",
+        from.format("%Y-%m-%d").to_string().bright_cyan().bold(),
+        until.format("%Y-%m-%d").to_string().bright_cyan().bold(),
         timezone.name().bright_green().bold()
     );
-    if summary {
-        let start_date = now - Duration::days(past_days as i64);
-        let end_date = now + Duration::days(future_days as i64);
-
-        let events_in_range: Vec<&ScheduleEvent> = events
-            .iter()
-            .filter(|event| {
-                event.start_time >= start_date.with_timezone(&Utc) && event.end_time <= end_date.with_timezone(&Utc)
-            })
-            .collect();
-        
-        let mut project_summary: HashMap<String, Duration> = HashMap::new();
 
-        for event in events_in_range.clone() {
-            let (project, _) = event.summary.split_once(':').unwrap_or(("", &event.summary));
-            let duration = event.end_time - event.start_time;
-            *project_summary.entry(project.to_string()).or_insert(Duration::zero()) += duration;
+    let mut days: BTreeMap<NaiveDate, Vec<&ScheduleEvent>> = BTreeMap::new();
+    for event in events.iter().filter(|event| event.booked || !booked_only) {
+        let date = event.start_time.with_timezone(timezone).date_naive();
+        if from <= date && date <= until {
+            days.entry(date).or_default().push(event);
         }
+    }
+    if days.is_empty() {
+        println!("{}", "No events found".yellow());
+        return;
+    }
 
-        let mut travel_info: Vec<String> = Vec::new();
-        let mut last_location: Option<String> = None;
-        for event in events_in_range {
-            if let Some(location) = &event.location {
-                if last_location.as_ref() != Some(location) {
-                    if let Some(last_loc) = last_location {
-                        travel_info.push(last_loc);
-                    }
-                    last_location = Some(location.clone());
-                }
-            }
-        }
-        if let Some(last_loc) = last_location {
-            travel_info.push(last_loc);
+    let share = |part: Duration, total: Duration| if total > Duration::zero() {
+        part.num_minutes() as f64 / total.num_minutes() as f64 * 100.0
+    } else {
+        0.0
+    };
+
+    println!("{}", format!("{:<14} {:>6} {:>8} {:>8} {:>8} {:>8} {:>5} {:>8} {:>6}", "Date", "Events", "Total", "Avg", "Longest", "Switches", "Gaps", "Free", "<30m").bright_blue().bold());
+    let mut period = DayStats::default();
+    let mut switches = 0;
+    for (date, day_events) in days.iter_mut() {
+        day_events.sort_by_key(|event| event.start_time);
+        let stats = day_stats(day_events);
+        println!(
+            "{:<14} {:>6} {:>8} {:>8} {:>8} {:>8} {:>5} {:>8} {:>5.0}%",
+            date.format("%Y-%m-%d %a").to_string(),
+            stats.events,
+            format_duration(stats.total, false),
+            format_duration(stats.total / stats.events as i32, false),
+            format_duration(stats.longest_block, false),
+            stats.context_switches,
+            stats.gaps,
+            format_duration(stats.free, false),
+            share(stats.short_blocks, stats.total),
+        );
+        period.events += stats.events;
+        period.total += stats.total;
+        period.longest_block = period.longest_block.max(stats.longest_block);
+        period.short_blocks += stats.short_blocks;
+        period.gaps += stats.gaps;
+        period.free += stats.free;
+        switches += stats.context_switches;
+    }
+
+    let day_count = days.len() as f64;
+    println!();
+    println!("{}", "Summary".yellow().bold());
+    println!("  {}", format!("Days with events     : {}", days.len()).bright_white());
+    println!("  {}", format!("Average event length : {}", format_duration(period.total / period.events as i32, false)).bright_white());
+    println!("  {}", format!("Longest block        : {}", format_duration(period.longest_block, false)).bright_green());
+    println!("  {}", format!("Context switches     : {} ({:.1} per day)", switches, switches as f64 / day_count).bright_white());
+    println!("  {}", format!("Gaps                 : {} ({} free)", period.gaps, format_duration(period.free, false)).bright_white());
+    println!("  {}", format!("Time in blocks < {}m : {:.0}%", SHORT_BLOCK_MINUTES, share(period.short_blocks, period.total)).bright_yellow());
+    println!();
+}
+
+#[derive(Default, Clone, Copy)]
+struct PlanAccuracy {
+    planned_booked: Duration,
+    missed: Duration,
+    open: Duration,
+    unplanned: Duration,
+    unknown: Duration,
+}
+
+impl PlanAccuracy {
+    fn add(&mut self, event: &ScheduleEvent, now: DateTime<Utc>) {
+        let duration = event.end_time - event.start_time;
+        match (event.booked, event.planned_ahead) {
+            (true, Some(true)) => self.planned_booked += duration,
+            (true, Some(false)) => self.unplanned += duration,
+            (true, None) => self.unknown += duration,
+            (false, _) if event.end_time < now => self.missed += duration,
+            (false, _) => self.open += duration,
         }
-        if travel_info.len() >= 1 {
-            println!("\n{} ({})", "Summary:".bright_yellow().bold(), travel_info.join(" → ").bright_blue().italic());
+    }
+
+    fn planned(&self) -> Duration {
+        self.planned_booked + self.missed + self.open
+    }
+
+    // Share of the planned and already past time which was actually booked
+    fn accuracy(&self) -> Option<f64> {
+        let due = self.planned_booked + self.missed;
+        if due > Duration::zero() {
+            Some(self.planned_booked.num_minutes() as f64 / due.num_minutes() as f64 * 100.0)
         } else {
-            println!("\n{}", "Summary:".bright_yellow().bold());
-        }
-        for (project, duration) in project_summary {
-            println!("  {}: {}", project.bright_blue(), format_duration(duration, true));
+            None
         }
     }
-    println!("\n");
-    print_events_grouped_by_day(events, timezone, past_days, date_str.clone(), true);
-    print_events_grouped_by_day(events, timezone, future_days, date_str, false);
-    // print_events_grouped_by_day(&filtered_events, timezone);
+
+    fn merge(&mut self, other: &Self) {
+        self.planned_booked += other.planned_booked;
+        self.missed += other.missed;
+        self.open += other.open;
+        self.unplanned += other.unplanned;
+        self.unknown += other.unknown;
+    }
 }
 
-fn generate_report(events: &[ScheduleEvent], project: &str, timezone: &Tz, month: Option<u32>, year: Option<i32>, target_time: Option<f64>) {
-    let now = Utc::now().with_second(0).unwrap().with_nanosecond(0).unwrap().with_timezone(timezone);
-    let current_year = now.year();
-    let current_month = now.month();
+fn format_plan_accuracy_line(label: &str, accuracy: &PlanAccuracy) -> String {
+    format!(
+        "{:<24} {:>8} {:>8} {:>8} {:>8} {:>10} {:>6}",
+        label,
+        format_duration(accuracy.planned(), false),
+        format_duration(accuracy.planned_booked, false),
+        format_duration(accuracy.missed, false),
+        format_duration(accuracy.open, false),
+        format_duration(accuracy.unplanned, false),
+        accuracy.accuracy().map(|percentage| format!("{:.0}%", percentage)).unwrap_or_else(|| "-".to_string()),
+    )
+}
 
-    let year = year.unwrap_or(current_year);
-    let month = month.unwrap_or(current_month);
+fn print_accuracy(events: &[ScheduleEvent], last_week: NaiveDate, weeks: u32, timezone: &Tz) {
+    let first_week = last_week - Duration::weeks(weeks.saturating_sub(1) as i64);
+    let end = last_week + Duration::days(6);
+    let now = Utc::now().with_second(0).unwrap().with_nanosecond(0).unwrap();
 
-    println!("+------------------------");
-    println!("|{}", format!("Report for Project: {}", project).bright_blue().bold());
-    println!("|{}", format!("Month/Year: {}/{}", month, year).bright_yellow().bold());
-    println!("|{}", format!("Timezone: {}", timezone.name()).yellow());
-    println!("{}\n", "+---------------".dimmed()); // Use dimmed for separator
-    let project_events: Vec<&ScheduleEvent> = events
-        .iter()
-        .filter(|event| {
-            let event_time = event.start_time.with_timezone(timezone);
-            event.summary.starts_with(&format!("{}:", project)) &&
-            event_time.year() == year &&
-            event_time.month() == month
-        })
-        .collect();
+    println!(
+        "Plan accuracy from {} to {} in timezone: {}\n",
+        first_week.format("%Y-%m-%d").to_string().bright_cyan().bold(),
+        end.format("%Y-%m-%d").to_string().bright_cyan().bold(),
+        timezone.name().bright_green().bold()
+    );
 
-    if project_events.is_empty() {
-        println!("{}", format!("No events found for project {} in {}/{}", project, month, year).yellow());
+    let mut weekly: BTreeMap<NaiveDate, BTreeMap<String, PlanAccuracy>> = BTreeMap::new();
+    let mut projects: BTreeMap<String, PlanAccuracy> = BTreeMap::new();
+    let mut has_unknown = false;
+    for event in events {
+        let date = event.start_time.with_timezone(timezone).date_naive();
+        if date < first_week || date > end {
+            continue;
+        }
+        let monday = date - Duration::days(date.weekday().num_days_from_monday() as i64);
+        let project = event.summary.split(':').next().unwrap_or_default().to_string();
+        weekly.entry(monday).or_default().entry(project.clone()).or_default().add(event, now);
+        projects.entry(project).or_default().add(event, now);
+        has_unknown |= event.booked && event.planned_ahead.is_none();
+    }
+
+    if weekly.is_empty() {
+        println!("{}", "No events found".yellow());
         return;
     }
 
-    let mut tasks: HashMap<&str, Vec<&ScheduleEvent>> = HashMap::new();
+    let header = format!("{:<24} {:>8} {:>8} {:>8} {:>8} {:>10} {:>6}", "Project", "Planned", "Booked", "Missed", "Open", "Unplanned", "Hit");
+    for (monday, week_projects) in &weekly {
+        let week = monday.iso_week();
+        println!("{}", format!("Week {}-W{:02}", week.year(), week.week()).bright_blue().bold());
+        println!("{}", header.dimmed());
+        let mut week_total = PlanAccuracy::default();
+        for (project, accuracy) in week_projects {
+            println!("{}", format_plan_accuracy_line(project, accuracy));
+            week_total.merge(accuracy);
+        }
+        println!("{}\n", format_plan_accuracy_line("Total", &week_total).bright_white().bold());
+    }
 
-    let mut planned_time = Duration::zero();
-    let mut booked_time = Duration::zero();
-    for event in &project_events {
-        let duration = event.end_time - event.start_time;
-        if event.booked {
-            booked_time = booked_time + duration;
+    println!("{}", "Summary per project".yellow().bold());
+    println!("{}", header.dimmed());
+    let mut total = PlanAccuracy::default();
+    for (project, accuracy) in &projects {
+        let line = format_plan_accuracy_line(project, accuracy);
+        if accuracy.unplanned > accuracy.planned_booked {
+            println!("{}", line.red()); // Systematically under-planned
         } else {
-            planned_time = planned_time + duration;
+            println!("{}", line);
         }
-        let task = event.summary.split_once(':').unwrap_or(("", "")).1;
-        tasks.entry(task).or_insert(Vec::new()).push(event);
+        total.merge(accuracy);
+    }
+    println!("{}", format_plan_accuracy_line("Total", &total).bright_white().bold());
+    if has_unknown {
+        println!("\n  {}", format!("{} booked without planning information (older events)", format_duration(total.unknown, false)).dimmed());
     }
+    println!();
+}
 
+const CHART_PROJECT_SYMBOLS: [char; 4] = ['█', '▓', '▒', '░'];
+const CHART_PROJECT_COLORS: [Color; 6] = [Color::Blue, Color::Green, Color::Yellow, Color::Magenta, Color::Cyan, Color::Red];
 
-    let mut sum_total_duration = Duration::zero();
-    for (task, task_events) in tasks {
-        let total_duration: Duration = task_events.iter().map(|event| event.end_time - event.start_time).sum();
-        sum_total_duration = sum_total_duration + total_duration;
+fn terminal_width() -> usize {
+    let (_, columns) = console::Term::stdout().size();
+    (columns as usize).max(40)
+}
 
-        println!("{}", format!("Task: {}", task).green().bold());
-        println!("  {}", format!("Total Time: {}", format_duration(total_duration, false)).bright_white());
+fn daily_totals(events: &[ScheduleEvent], timezone: &Tz, include_planned: bool) -> HashMap<NaiveDate, Duration> {
+    let mut totals: HashMap<NaiveDate, Duration> = HashMap::new();
+    for event in events.iter().filter(|event| event.booked || include_planned) {
+        *totals.entry(event.start_time.with_timezone(timezone).date_naive()).or_insert(Duration::zero()) += event.end_time - event.start_time;
+    }
+    totals
+}
 
-        for event in task_events {
-            let duration = event.end_time - event.start_time;
-            let booked = if event.booked {
-                "[✔]".green()
+// GitHub style heatmap of the last year, one column per week
+fn print_heatmap(totals: &HashMap<NaiveDate, Duration>, until: NaiveDate, width: usize) {
+    let last_monday = until - Duration::days(until.weekday().num_days_from_monday() as i64);
+    let cell_width = if width >= 6 + 53 * 2 { 2 } else { 1 };
+    let weeks = ((width - 6) / cell_width).min(53) as i64;
+    let first_monday = last_monday - Duration::weeks(weeks - 1);
+
+    println!("{}", format!("Booked hours per day ({} - {})", first_monday.format("%Y-%m-%d"), until.format("%Y-%m-%d")).bright_yellow().bold());
+    let mut months = String::from("      ");
+    let mut last_month = 0;
+    for week in 0..weeks {
+        let monday = first_monday + Duration::weeks(week);
+        let position = 6 + week as usize * cell_width;
+        if monday.month() != last_month {
+            // Skip the label if the previous one is still too close
+            if months.len() < position || position == 6 {
+                months.push_str(&" ".repeat(position - months.len()));
+                months.push_str(&monday.format("%b").to_string());
+            }
+            last_month = monday.month();
+        }
+    }
+    println!("{}", months.dimmed());
+
+    for weekday in 0..7 {
+        let mut line = format!("{:<6}", if weekday % 2 == 0 { Weekday::try_from(weekday as u8).map(|day| day.to_string()).unwrap_or_default() } else { String::new() });
+        for week in 0..weeks {
+            let date = first_monday + Duration::weeks(week) + Duration::days(weekday);
+            let cell = if date > until {
+                " ".normal()
             } else {
-                if event.end_time.with_timezone(timezone) < Utc::now().with_second(0).unwrap().with_nanosecond(0).unwrap().with_timezone(timezone) {
-                    "[✗]".red()
-                } else {
-                    "[≈]".blue()
+                let hours = totals.get(&date).map(|duration| duration.num_minutes()).unwrap_or(0) as f64 / 60.0;
+                match hours {
+                    h if h <= 0.0 => "·".dimmed(),
+                    h if h < 2.0 => "░".green(),
+                    h if h < 4.0 => "▒".green(),
+                    h if h < 6.0 => "▓".bright_green(),
+                    _ => "█".bright_green(),
                 }
             };
-            println!(
-                "    {} - {} ({}) {} {} ({})",
-                event.start_time.with_timezone(timezone).format("%Y-%m-%d %H:%M"),
-                event.end_time.with_timezone(timezone).format("%H:%M"),
-                format_duration(duration, false),
-                booked,
-                event.note.as_deref().unwrap_or_default(),
-                event.id.italic().dimmed(),
-            );
-         }
-         println!();
+            line.push_str(&format!("{}{}", cell, " ".repeat(cell_width - 1)));
+        }
+        println!("{}", line);
     }
-    println!("{}", "Summary".yellow().bold()); // Clearer section header
-    println!("  {}", format!("Total Time  : {}", format_duration(sum_total_duration, false)).bright_white().bold());
-    println!("  {}", format!("Planned Time: {}", format_duration(planned_time, false)).bright_blue());
-    println!("  {}", format!("Booked Time : {}", format_duration(booked_time, false)).bright_green());
+    println!("      {} 0h {} <2h {} <4h {} <6h {} 6h+\n", "·".dimmed(), "░".green(), "▒".green(), "▓".bright_green(), "█".bright_green());
+}
 
-    if let Some(target) = target_time {
-        let target_duration = Duration::minutes((target * 60.0) as i64);
-        let diff = sum_total_duration - target_duration;
+// Stacked bars of the project shares per week
+fn print_week_bars(events: &[ScheduleEvent], until: NaiveDate, weeks: u32, timezone: &Tz, include_planned: bool, width: usize) {
+    let last_monday = until - Duration::days(until.weekday().num_days_from_monday() as i64);
+    let first_monday = last_monday - Duration::weeks(weeks.saturating_sub(1) as i64);
+
+    let mut week_projects: BTreeMap<NaiveDate, BTreeMap<String, Duration>> = BTreeMap::new();
+    let mut projects: BTreeMap<String, Duration> = BTreeMap::new();
+    for event in events.iter().filter(|event| event.booked || include_planned) {
+        let date = event.start_time.with_timezone(timezone).date_naive();
+        if date < first_monday || date > until {
+            continue;
+        }
+        let monday = date - Duration::days(date.weekday().num_days_from_monday() as i64);
+        let project = event.summary.split(':').next().unwrap_or_default().to_string();
+        let duration = event.end_time - event.start_time;
+        *week_projects.entry(monday).or_default().entry(project.clone()).or_insert(Duration::zero()) += duration;
+        *projects.entry(project).or_insert(Duration::zero()) += duration;
+    }
 
-        let diff_hours = diff.num_hours();
-        let diff_minutes = diff.num_minutes() % 60;
+    println!("{}", "Project share per week".bright_yellow().bold());
+    if projects.is_empty() {
+        println!("    {}\n", "No events".italic());
+        return;
+    }
 
-        let diff_str = if diff > Duration::zero() {
-            format!("Overrun     : {diff_hours}h {diff_minutes}m").green()
-        } else {
-            format!("Underrun    : {}h {}m", -diff_hours, -diff_minutes).red()
-        };
+    let styles: HashMap<&str, (char, Color)> = projects
+        .keys()
+        .enumerate()
+        .map(|(index, project)| (project.as_str(), (CHART_PROJECT_SYMBOLS[index % CHART_PROJECT_SYMBOLS.len()], CHART_PROJECT_COLORS[index % CHART_PROJECT_COLORS.len()])))
+        .collect();
+    let max_week = week_projects.values().map(|week| week.values().copied().sum::<Duration>()).max().unwrap_or(Duration::zero());
+    let bar_width = width.saturating_sub(20).max(10);
+
+    for week in 0..weeks {
+        let monday = first_monday + Duration::weeks(week as i64);
+        let iso_week = monday.iso_week();
+        let mut bar = String::new();
+        let mut total = Duration::zero();
+        if let Some(week_data) = week_projects.get(&monday) {
+            total = week_data.values().copied().sum();
+            for (project, duration) in week_data {
+                let cells = (duration.num_minutes() as f64 / max_week.num_minutes() as f64 * bar_width as f64).round() as usize;
+                let (symbol, color) = styles[project.as_str()];
+                bar.push_str(&symbol.to_string().repeat(cells).color(color).to_string());
+            }
+        }
+        println!("{} {} {}", format!("{}-W{:02}", iso_week.year(), iso_week.week()).dimmed(), format!("{:>6}", format_duration(total, false)).bright_white(), bar);
+    }
+    let legend = projects
+        .keys()
+        .map(|project| {
+            let (symbol, color) = styles[project.as_str()];
+            format!("{} {}", symbol.to_string().color(color), project)
+        })
+        .join("  ");
+    println!("         {}\n", legend);
+}
 
-        let percentage_diff = if target_duration != Duration::zero() {
-            (diff.num_minutes() as f64 / target_duration.num_minutes() as f64) * 100.0
-        } else {
-            0.0 // Avoid division by zero if target is zero.
-        };
+// Sparkline of the daily totals, as many days as fit into the terminal
+fn print_sparkline(totals: &HashMap<NaiveDate, Duration>, until: NaiveDate, width: usize) {
+    const SPARKS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+    let days = width.saturating_sub(20).clamp(7, 365) as i64;
+    let first = until - Duration::days(days - 1);
+    let values: Vec<i64> = (0..days)
+        .map(|day| totals.get(&(first + Duration::days(day))).map(|duration| duration.num_minutes()).unwrap_or(0))
+        .collect();
+    let max = values.iter().copied().max().unwrap_or(0);
 
-        let target_hours = target_duration.num_hours();
-        let target_minutes = target_duration.num_minutes() % 60;
-        let target_str = format!("{target_hours}h {target_minutes}m");
-        // println!("  Time difference: {} of {} ({:.1}%)", diff_str, target_str.blue(), percentage_diff);
-        println!("  {}", format!("Target time : {}", target_str).bright_cyan());
-        println!("  {}", format!("{} ({:.1}%)", diff_str, percentage_diff).bright_white());
-    }
-    println!("");
+    println!("{}", format!("Daily totals ({} - {})", first.format("%Y-%m-%d"), until.format("%Y-%m-%d")).bright_yellow().bold());
+    let line: String = values
+        .iter()
+        .map(|minutes| {
+            if *minutes == 0 || max == 0 {
+                ' '
+            } else {
+                SPARKS[((*minutes as f64 / max as f64) * (SPARKS.len() - 1) as f64).round() as usize]
+            }
+        })
+        .collect();
+    let total: i64 = values.iter().sum();
+    let working_days = values.iter().filter(|minutes| **minutes > 0).count().max(1);
+    println!("{}", line.bright_cyan());
+    println!(
+        "{}\n",
+        format!(
+            "max {} / avg {} per working day",
+            format_duration(Duration::minutes(max), false),
+            format_duration(Duration::minutes(total / working_days as i64), false)
+        ).dimmed()
+    );
 }
 
 fn cleanup_events(events: &mut Vec<ScheduleEvent>, days: u32) {
@@ -990,6 +4818,47 @@ fn cleanup_events(events: &mut Vec<ScheduleEvent>, days: u32) {
     events.retain(|event| event.end_time > cutoff_date);
 }
 
+#[derive(Serialize)]
+struct FreeOutput<'a> {
+    start: String,
+    end: String,
+    free: bool,
+    status: &'static str,
+    conflicts: Vec<EventOutput<'a>>,
+    events: Vec<EventOutput<'a>>,
+}
+
+// Result of free for json and tsv output, tsv only lists the conflicting events
+fn print_free_output(start_time: DateTime<Utc>, end_time: DateTime<Utc>, conflicting_events: &[ScheduleEvent], busy_events: &[ScheduleEvent], timezone: &Tz, output: OutputFormat) -> Result<(), Error> {
+    let conflicts: Vec<&ScheduleEvent> = conflicting_events.iter().collect();
+    if output == OutputFormat::Tsv {
+        print_events_tsv(&conflicts, timezone);
+        return Ok(());
+    }
+    let status = if conflicts.is_empty() {
+        "free"
+    } else if conflicts.iter().all(|event| event.overlay.is_some()) {
+        "overlay"
+    } else if conflicts.iter().any(|event| event.booked) {
+        "booked"
+    } else {
+        "planned"
+    };
+    let date = start_time.with_timezone(timezone).date_naive();
+    print_json(&FreeOutput {
+        start: start_time.with_timezone(timezone).to_rfc3339(),
+        end: end_time.with_timezone(timezone).to_rfc3339(),
+        free: conflicts.is_empty(),
+        status,
+        conflicts: conflicts.iter().map(|event| EventOutput::new(event, timezone)).collect(),
+        events: busy_events
+            .iter()
+            .filter(|event| event.start_time.with_timezone(timezone).date_naive() == date)
+            .map(|event| EventOutput::new(event, timezone))
+            .collect(),
+    })
+}
+
 fn is_slot_free(events: &[ScheduleEvent], start_time: DateTime<Utc>, end_time: DateTime<Utc>) -> Result<bool, Vec<ScheduleEvent>> {
     let conflicting_events: Vec<ScheduleEvent> = events
         .iter()
@@ -1004,90 +4873,61 @@ fn is_slot_free(events: &[ScheduleEvent], start_time: DateTime<Utc>, end_time: D
     }
 }
 
-fn delete_event(events: &mut Vec<ScheduleEvent>, id: &str, timespan: Option<String>, rounding: u32, timezone: &Tz) -> Result<bool, Error> {
-    let event_index = events.iter().position(|event| event.id == id);
-
-    if let Some(index) = event_index {
-        if let Some(timespan_str) = timespan {
-            let original_event = events[index].clone();
-            let event_date = original_event.start_time.date_naive().format("%Y-%m-%d");
-            let (start_remove, end_remove) = parse_datetime_range(&timespan_str, Some(event_date.to_string().as_str()), rounding, timezone)?;
-
-
-            if start_remove >= original_event.end_time || end_remove <= original_event.start_time {
-                println!("{}", "Specified timespan does not overlap with the event.".yellow());
-                return Ok(false); // No changes made
-            }
-
-
-            let mut modified_events = Vec::new();
-
-            if start_remove > original_event.start_time {
-                modified_events.push(ScheduleEvent {
-                    id: Uuid::new_v4().to_string(),
-                    start_time: original_event.start_time,
-                    end_time: start_remove,
-                    summary: original_event.summary.clone(),
-                    note: original_event.note.clone(),
-                    location: original_event.location.clone(),
-                    booked: original_event.booked,
-                });
-            }
-
-            if end_remove < original_event.end_time {
-                modified_events.push(ScheduleEvent {
-                    id: Uuid::new_v4().to_string(),
-                    start_time: end_remove,
-                    end_time: original_event.end_time,
-                    summary: original_event.summary.clone(),
-                    note: original_event.note.clone(),
-                    location: original_event.location.clone(),
-                    booked: original_event.booked,
-                });
-            }
-
-            print_event_diff(&[original_event], &modified_events, &timezone);
-
-            let confirmed = Confirm::with_theme(&ColorfulTheme::default())
-                .with_prompt("Apply these changes?")
-                .interact();
-
-            if confirmed.is_err() || !confirmed.unwrap() {
-                println!("{}", "Changes not applied".yellow());
-                return Ok(false); // No changes made
-            }
-
-
-            events.remove(index);
-            events.extend(modified_events);
-            events.sort_by_key(|e| e.start_time);
-            merge_events(events);
-
-
-        } else {
-            let original_event = &events[index];
-
-            println!("{}", "Deleting the following event:".yellow().bold());
-            println!("- {}", format_event_for_diff(&original_event, &timezone).red());
-
-            let confirmed = Confirm::with_theme(&ColorfulTheme::default())
-                .with_prompt("Delete this event?")
-                .interact();
+// Removes the event, or only the timespan from it. The first remaining part keeps the UID, so calendar clients see an update
+fn delete_event(events: &mut Vec<ScheduleEvent>, id: &str, timespan: Option<&str>, rounding: u32, timezone: &Tz) -> Result<(), Error> {
+    let index = events.iter().position(|event| event.id == id).ok_or_else(|| {
+        Error::new(ErrorKind::NotFound, format!("Event with ID {} not found", id))
+    })?;
 
+    if let Some(timespan_str) = timespan {
+        let original_event = events[index].clone();
+        let event_date = original_event.start_time.date_naive().format("%Y-%m-%d");
+        let (start_remove, end_remove) = parse_datetime_range(timespan_str, Some(event_date.to_string().as_str()), rounding, timezone)?;
 
-            if confirmed.is_err() || !confirmed.unwrap() {
-                println!("{}", "Event not deleted".yellow());
-                return Ok(false);
-            }
+        if start_remove >= original_event.end_time || end_remove <= original_event.start_time {
+            return Err(Error::new(ErrorKind::InvalidInput, "Specified timespan does not overlap with the event."));
+        }
 
-            events.remove(index);
+        let mut modified_events = Vec::new();
+
+        if start_remove > original_event.start_time {
+            modified_events.push(ScheduleEvent {
+                id: original_event.id.clone(),
+                start_time: original_event.start_time,
+                end_time: start_remove,
+                summary: original_event.summary.clone(),
+                note: original_event.note.clone(),
+                location: original_event.location.clone(),
+                booked: original_event.booked,
+                tags: original_event.tags.clone(),
+                planned_ahead: original_event.planned_ahead,
+                overlay: None,
+            });
+        }
 
+        if end_remove < original_event.end_time {
+            modified_events.push(ScheduleEvent {
+                id: if modified_events.is_empty() { original_event.id.clone() } else { Uuid::new_v4().to_string() },
+                start_time: end_remove,
+                end_time: original_event.end_time,
+                summary: original_event.summary.clone(),
+                note: original_event.note.clone(),
+                location: original_event.location.clone(),
+                booked: original_event.booked,
+                tags: original_event.tags.clone(),
+                planned_ahead: original_event.planned_ahead,
+                overlay: None,
+            });
         }
-        Ok(true) // Changes made
+
+        events.remove(index);
+        events.extend(modified_events);
+        events.sort_by_key(|e| e.start_time);
+        merge_events(events);
     } else {
-        println!("Event with ID {} not found", id.green().bold());
-        Ok(false) // No changes made
+        events.remove(index);
     }
+    Ok(())
 }
 
 fn find_next_event_time(events: &[ScheduleEvent], project_task: &str, duration_minutes: u32, now: DateTime<Utc>) -> Result<(DateTime<Utc>, DateTime<Utc>), Error> {
@@ -1130,26 +4970,188 @@ fn find_free_slot(
         if is_slot_free(events, current_time, proposed_end_time).is_ok() {
             return Ok((current_time, proposed_end_time));
         }
-        current_time = current_time + duration;
+        current_time += duration;
     }
 
-    Err(Error::new(ErrorKind::Other, "Could not find free slot."))
+    Err(Error::other("Could not find free slot."))
 }
 
 fn round_duration_up(duration: Duration, interval: u32) -> Duration {
     let minutes = duration.num_minutes();
     let remainder = minutes % interval as i64;
     if remainder > 0 {
-        duration + Duration::minutes((interval as i64 - remainder) as i64)
+        duration + Duration::minutes(interval as i64 - remainder)
     } else {
         duration
     }
 }
 
-fn main() -> Result<(), Error> {
-    let args = Args::parse();
+// Shell functions completing data from the schedule, falling back to the generated completions
+const BASH_DYNAMIC_COMPLETION: &str = r#"
+_plantrack_data() {
+    local values
+    if values="$(plantrack __complete bash "${COMP_LINE:0:COMP_POINT}" 2>/dev/null)"; then
+        local IFS=$'\n'
+        COMPREPLY=($values)
+        return 0
+    fi
+    _plantrack "$@"
+}
+complete -F _plantrack_data -o bashdefault -o default plantrack
+"#;
+
+const ZSH_DYNAMIC_COMPLETION: &str = r#"
+_plantrack_data() {
+    local output
+    if output="$(plantrack __complete zsh "${(j: :)words[1,CURRENT]}" 2>/dev/null)"; then
+        local -a values
+        values=("${(@f)output}")
+        [[ -n "$output" ]] && _describe 'value' values
+        return 0
+    fi
+    _plantrack "$@"
+}
+compdef _plantrack_data plantrack
+"#;
+
+const FISH_DYNAMIC_COMPLETION: &str = r#"
+complete -c plantrack -f -n 'plantrack __complete fish (commandline -cp) >/dev/null 2>&1' -a '(plantrack __complete fish (commandline -cp) 2>/dev/null)'
+"#;
+
+fn print_completions(shell: Shell) {
+    let mut command = Args::command();
+    generate(shell, &mut command, APP_NAME, &mut std::io::stdout());
+    match shell {
+        Shell::Bash => print!("{}", BASH_DYNAMIC_COMPLETION),
+        Shell::Zsh => print!("{}", ZSH_DYNAMIC_COMPLETION),
+        Shell::Fish => print!("{}", FISH_DYNAMIC_COMPLETION),
+        _ => {}
+    }
+}
 
-    let config_path = match args.config_file {
+// Value of a global option given before the subcommand on the command line
+fn global_option<'a>(words: &[&'a str], short: &str, long: &str) -> Option<&'a str> {
+    let position = words.iter().position(|word| *word == short || *word == long)?;
+    words.get(position + 1).copied()
+}
+
+// Candidates with description for the last word of the command line, None if it is not completed from the schedule
+fn complete_values(words: &[&str], current: &str, events: &[ScheduleEvent], config: &Config, timezone: &Tz) -> Option<Vec<(String, String)>> {
+    if current.starts_with('-') {
+        return None;
+    }
+    let command = Args::command();
+    let takes_value = |command: &clap::Command, option: &str| {
+        command
+            .get_arguments()
+            .find(|arg| arg.get_long().is_some_and(|long| option == format!("--{}", long)) || arg.get_short().is_some_and(|short| option == format!("-{}", short)))
+            .filter(|arg| arg.get_action().takes_values())
+            .map(|arg| arg.get_id().to_string())
+    };
+
+    // Either the value of the option before the current word, or the next positional argument of the subcommand
+    let mut kind = None;
+    let mut subcommand = None;
+    let mut positionals = 0;
+    let mut index = 1;
+    while index < words.len() {
+        let word = words[index];
+        if word.starts_with('-') {
+            if let Some(option) = takes_value(subcommand.unwrap_or(&command), word) {
+                if index + 1 == words.len() {
+                    kind = Some(option);
+                }
+                index += 1;
+            }
+        } else if subcommand.is_none() {
+            subcommand = Some(command.find_subcommand(word)?);
+        } else {
+            positionals += 1;
+        }
+        index += 1;
+    }
+    let kind = match kind {
+        Some(kind) => kind,
+        None => subcommand?.get_positionals().nth(positionals)?.get_id().to_string(),
+    };
+
+    let summaries = || events.iter().map(|event| event.summary.clone()).unique().sorted();
+    let candidates: Vec<(String, String)> = match kind.as_str() {
+        "project_task" | "in_project_task" => summaries().map(|summary| (summary, String::new())).collect(),
+        "project" => summaries()
+            .flat_map(|summary| {
+                let segments: Vec<String> = summary.split(':').map(String::from).collect();
+                (1..=segments.len()).map(move |depth| segments[..depth].join(":"))
+            })
+            .unique()
+            .sorted()
+            .map(|path| (path, String::new()))
+            .collect(),
+        "id" => events
+            .iter()
+            .sorted_by_key(|event| std::cmp::Reverse(event.start_time))
+            .map(|event| {
+                let start_time = event.start_time.with_timezone(timezone);
+                let end_time = event.end_time.with_timezone(timezone);
+                (event.id.clone(), format!("{} - {} {}", start_time.format("%Y-%m-%d %H:%M"), end_time.format("%H:%M"), event.summary))
+            })
+            .collect(),
+        "location" => events
+            .iter()
+            .filter_map(|event| event.location.clone())
+            .chain(config.locations.keys().cloned())
+            .chain(config.home_locations.iter().cloned())
+            .unique()
+            .sorted()
+            .map(|location| (location, String::new()))
+            .collect(),
+        "tags" | "untags" | "tag" => events
+            .iter()
+            .flat_map(|event| event.tags.iter().cloned())
+            .unique()
+            .sorted()
+            .map(|tag| (tag, String::new()))
+            .collect(),
+        "timezone" => chrono_tz::TZ_VARIANTS.iter().map(|tz| (tz.name().to_string(), String::new())).collect(),
+        _ => return None,
+    };
+    Some(candidates.into_iter().filter(|(value, _)| value.starts_with(current)).collect())
+}
+
+// Hidden __complete helper called by the shell functions of `completions`, exits with 1 to fall back to the generated completions
+fn complete(shell: Shell, line: &str, args: &Args) -> Result<ExitCode, Error> {
+    let mut words: Vec<&str> = line.split_whitespace().collect();
+    let current = if line.ends_with(char::is_whitespace) || words.len() < 2 { "" } else { words.pop().unwrap_or_default() };
+
+    let config_file = global_option(&words, "-c", "--config-file").map(PathBuf::from).or(args.config_file.clone());
+    // Loading a missing config would create it, the shell falls back to the static completions instead
+    let config_path = resolve_config_path(config_file)?;
+    if !config_path.exists() {
+        return Ok(ExitCode::FAILURE);
+    }
+    let config = Config::load(&config_path)?;
+    let events = load_events(&config.schedule_file)?;
+    let timezone = resolve_timezone(global_option(&words, "-t", "--timezone").or(args.timezone.as_deref()), &config)?;
+
+    let Some(candidates) = complete_values(&words, current, &events, &config, &timezone) else {
+        return Ok(ExitCode::FAILURE);
+    };
+    // Bash splits words at colons, so only the part after the last colon is completed
+    let colon_prefix = current.rfind(':').map_or(0, |position| position + 1);
+    for (value, description) in candidates {
+        match shell {
+            Shell::Bash => println!("{}", &value[colon_prefix..]),
+            Shell::Zsh if description.is_empty() => println!("{}", value.replace(':', "\\:")),
+            Shell::Zsh => println!("{}:{}", value.replace(':', "\\:"), description),
+            _ if description.is_empty() => println!("{}", value),
+            _ => println!("{}\t{}", value, description),
+        }
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+fn resolve_config_path(config_file: Option<PathBuf>) -> Result<PathBuf, Error> {
+    Ok(match config_file {
         Some(path) => {
             if path.is_absolute() {
                 path
@@ -1162,15 +5164,11 @@ fn main() -> Result<(), Error> {
             let config_dir = xdg_dirs.get_config_home();
             config_dir.join(DEFAULT_CONFIG_FILE) // Otherwise use the XDG directory
         }
-    };
-    let config = Config::load(&config_path)?; // Pass the resolved path to Config::load
-
-    let schedule_file_path = config.schedule_file;
-    let ics_file_path = config.ics_file;
-
-    let mut events: Vec<ScheduleEvent> = load_events(&schedule_file_path)?;
+    })
+}
 
-    let timezone: Tz = match args.timezone.as_deref() { // CLI argument has highest priority
+fn resolve_timezone(timezone: Option<&str>, config: &Config) -> Result<Tz, Error> {
+    Ok(match timezone { // CLI argument has highest priority
         Some(tz_str) => tz_str.parse().map_err(|_| {
             Error::new(ErrorKind::InvalidInput, "Invalid timezone format. Use IANA format (e.g., America/New_York).")
         })?,
@@ -1181,12 +5179,40 @@ fn main() -> Result<(), Error> {
                 None => config.timezone.as_deref().and_then(|tz_config| tz_config.parse().ok()).unwrap_or(Tz::UTC) // Then config, finally UTC
             }
         },
-    };
+    })
+}
+
+fn main() -> Result<ExitCode, Error> {
+    let args = Args::parse();
+
+    // Completions work without a config, the helper reads the config given on the completed command line
+    match &args.command {
+        Commands::Completions { shell } => {
+            print_completions(*shell);
+            return Ok(ExitCode::SUCCESS);
+        }
+        Commands::Complete { shell, line } => return complete(*shell, line, &args),
+        _ => {}
+    }
+
+    let config_path = resolve_config_path(args.config_file)?;
+    let config = Config::load(&config_path)?; // Pass the resolved path to Config::load
+
+    let schedule_file_path = config.schedule_file.clone();
 
-    let export_notes = config.export_notes.unwrap_or(true); // Read export_notes from config, defaulting to true
+    let mut events: Vec<ScheduleEvent> = load_events(&schedule_file_path)?;
+
+    let timezone = resolve_timezone(args.timezone.as_deref(), &config)?;
 
     let rounding = args.rounding.or(config.rounding).unwrap_or(15); // Rounding handling: CLI > Config > Default (15)
 
+    // Commands which reach the end of main after changing the schedule
+    let mutating = matches!(args.command, Commands::Add { .. } | Commands::Quickadd { .. } | Commands::Todo { .. } | Commands::Import { .. } | Commands::Cleanup { .. } | Commands::Set { .. } | Commands::Delete { .. } | Commands::Sync { .. });
+    // Other commands retry a failed push first, mutating ones after their change, current is used in prompts and never waits
+    if !mutating && !matches!(args.command, Commands::Push { .. } | Commands::Current { .. }) {
+        retry_push(&config, false);
+    }
+
     match args.command {
         Commands::Add {
             project_task,
@@ -1195,13 +5221,14 @@ fn main() -> Result<(), Error> {
             note,
             location,
             booked,
+            tags,
+            yes,
+            output,
         } => {
             let (start_time, end_time) = parse_datetime_range(&timespan, date.as_deref(), rounding, &timezone)?;
-            let (project, task) = project_task
-                .split_once(':')
-                .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "Invalid project:task format"))?;
-            let summary = format!("{}:{}", project.trim(), task.trim());
+            let summary = parse_project_path(&project_task)?;
 
+            let tags = collect_tags(&[], &tags, note.as_deref());
             let event = ScheduleEvent {
                 id: Uuid::new_v4().to_string(),
                 start_time,
@@ -1210,21 +5237,26 @@ fn main() -> Result<(), Error> {
                 note,
                 location,
                 booked,
+                tags,
+                planned_ahead: Some(!booked),
+                overlay: None,
             };
 
-            let overlaps = split_overlapping_events(&mut events, event.clone(), &timezone);
-            if !overlaps {
-                println!("{}", "New event:".yellow().bold());
-                println!("+ {}", format_event_for_diff(&event,&timezone).green());
-            }
+            let overlay_events = load_overlays(&config.overlays, &timezone);
+            let original_events = events.clone();
+            let overlaps = add_event(&mut events, &overlay_events, event, output, &timezone);
 
-            let confirmed = Confirm::with_theme(&ColorfulTheme::default())
+            let confirmed = yes || Confirm::with_theme(&ColorfulTheme::default())
                 .with_prompt(if overlaps { "Overlapping events found. Add anyway?" } else { "Add this event?" })
-                .interact();
+                .interact()
+                .unwrap_or(false);
 
-            if confirmed.is_err() || !confirmed.unwrap() {
-                println!("{}", "Event not added".yellow());
-                return Ok(());
+            if !confirmed {
+                if output == OutputFormat::Text {
+                    println!("{}", "Event not added".yellow());
+                }
+                print_changes(&original_events, &events, false, output, &timezone)?;
+                return Ok(ExitCode::from(EXIT_NOT_APPLIED));
             }
             // if overlaps {
             //     let confirmed = Confirm::with_theme(&ColorfulTheme::default())
@@ -1236,11 +5268,14 @@ fn main() -> Result<(), Error> {
             //         return Ok(()); // Exit early if the user cancels or an error occurs
             //     }
             // }
-            save_events(&schedule_file_path, &events)?;
-            generate_ics(&ics_file_path, &events, export_notes)?;
-            println!("{}", "Event added".green());
+            save_schedule(&config, &original_events, &events, &timezone)?;
+            export_ics(&config, &events)?;
+            if output == OutputFormat::Text {
+                println!("{}", "Event added".green());
+            }
+            print_changes(&original_events, &events, true, output, &timezone)?;
         }
-        Commands::Quickadd { project_task, minutes, note, location, forward } => {
+        Commands::Quickadd { project_task, minutes, note, location, forward, tags, yes, output } => {
             let now = Utc::now().with_second(0).unwrap().with_nanosecond(0).unwrap();
             let duration_minutes = round_duration_up(Duration::minutes(minutes.unwrap_or(rounding) as i64), rounding);
 
@@ -1256,11 +5291,9 @@ fn main() -> Result<(), Error> {
                 (start_time, end_time)
             };
 
-            let (project, task) = project_task
-                .split_once(':')
-                .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "Invalid project:task format"))?;
-            let summary = format!("{}:{}", project.trim(), task.trim());
+            let summary = parse_project_path(&project_task)?;
 
+            let tags = collect_tags(&[], &tags, note.as_deref());
             let event = ScheduleEvent {
                 id: Uuid::new_v4().to_string(),
                 start_time,
@@ -1269,21 +5302,26 @@ fn main() -> Result<(), Error> {
                 note,
                 location,
                 booked: true,
+                tags,
+                planned_ahead: Some(false),
+                overlay: None,
             };
 
-            let overlaps = split_overlapping_events(&mut events, event.clone(), &timezone);
-            if !overlaps {
-                println!("{}", "New event:".yellow().bold());
-                println!("+ {}", format_event_for_diff(&event, &timezone).green());
-            }
+            let overlay_events = load_overlays(&config.overlays, &timezone);
+            let original_events = events.clone();
+            let overlaps = add_event(&mut events, &overlay_events, event, output, &timezone);
 
-            let confirmed = Confirm::with_theme(&ColorfulTheme::default())
+            let confirmed = yes || Confirm::with_theme(&ColorfulTheme::default())
                 .with_prompt(if overlaps { "Overlapping events found. Add anyway?" } else { "Add this event?" })
-                .interact();
+                .interact()
+                .unwrap_or(false);
 
-            if confirmed.is_err() || !confirmed.unwrap() {
-                println!("{}", "Event not added".yellow());
-                return Ok(());
+            if !confirmed {
+                if output == OutputFormat::Text {
+                    println!("{}", "Event not added".yellow());
+                }
+                print_changes(&original_events, &events, false, output, &timezone)?;
+                return Ok(ExitCode::from(EXIT_NOT_APPLIED));
             }
             // if overlaps {
             //     let confirmed = Confirm::with_theme(&ColorfulTheme::default())
@@ -1298,11 +5336,14 @@ fn main() -> Result<(), Error> {
             // split_overlapping_events(&mut events, event.clone());
             // merge_events(&mut events);
 
-            save_events(&schedule_file_path, &events)?;
-            generate_ics(&ics_file_path, &events, export_notes)?;
-            println!("{}", "Event added".green());
+            save_schedule(&config, &original_events, &events, &timezone)?;
+            export_ics(&config, &events)?;
+            if output == OutputFormat::Text {
+                println!("{}", "Event added".green());
+            }
+            print_changes(&original_events, &events, true, output, &timezone)?;
         }
-        Commands::Todo { project_task, minutes, in_project_task, date, timespan, note, location } => {
+        Commands::Todo { project_task, minutes, in_project_task, date, timespan, note, location, tags } => {
             let rounding_interval = rounding;
             let duration_minutes = minutes.unwrap_or(rounding_interval * 2);
             let now = Utc::now().with_second(0).unwrap().with_nanosecond(0).unwrap();
@@ -1310,19 +5351,20 @@ fn main() -> Result<(), Error> {
             let (start_time, end_time) = if let Some(in_proj_task) = in_project_task {
                 find_next_event_time(&events, &in_proj_task, duration_minutes, now)?
             } else {
-                match find_free_slot(&events, &timespan, date.as_deref(), duration_minutes, rounding, &timezone) {
+                let busy_events: Vec<ScheduleEvent> = events.iter().chain(&load_overlays(&config.overlays, &timezone)).cloned().collect();
+                match find_free_slot(&busy_events, &timespan, date.as_deref(), duration_minutes, rounding, &timezone) {
                     Ok(slot) => slot,
                     Err(e) => {
                         println!("{}", e); // Indicate why no free slot could be found
-                        return Ok(()); // Do not attempt to create a todo if no free slot is available
+                        return Ok(ExitCode::from(EXIT_NOT_APPLIED)); // Do not attempt to create a todo if no free slot is available
                     }
 
                 }
             };
 
-            let (project, task) = project_task.split_once(':').ok_or(Error::new(ErrorKind::InvalidInput, "Invalid project:task format"))?;
-            let summary = format!("{}:{}", project.trim(), task.trim());
+            let summary = parse_project_path(&project_task)?;
 
+            let tags = collect_tags(&[], &tags, note.as_deref());
             let event = ScheduleEvent {
                 id: Uuid::new_v4().to_string(),
                 start_time,
@@ -1331,6 +5373,9 @@ fn main() -> Result<(), Error> {
                 note,
                 location,
                 booked: false,
+                tags,
+                planned_ahead: Some(true),
+                overlay: None,
             };
 
             println!("{} {}", "New todo on".yellow().bold(), format!("{}", event.start_time.date_naive()).yellow());
@@ -1340,33 +5385,225 @@ fn main() -> Result<(), Error> {
                 .with_prompt("Add this todo?")
                 .interact().unwrap()
             {
+                let original_events = events.clone();
                 split_overlapping_events(&mut events, event, &timezone);
-                save_events(&schedule_file_path, &events)?;
-                generate_ics(&ics_file_path, &events, export_notes)?;
+                save_schedule(&config, &original_events, &events, &timezone)?;
+                export_ics(&config, &events)?;
                 println!("{}", "Todo added".green());
             } else {
                 println!("{}", "Todo not added".yellow());
+                return Ok(ExitCode::from(EXIT_NOT_APPLIED));
+            }
+        }
+        Commands::List { past_days, future_days, date, summary, tag, project, output } => {
+            // Overlays only show up in the unfiltered list
+            let filtered = tag.is_some() || project.is_some();
+            if let Some(tag) = tag {
+                events.retain(|event| event.has_tag(&tag));
+            }
+            if let Some(project) = project {
+                events.retain(|event| matches_path(&event.summary, &project));
+            }
+            let overlay_events = if !filtered { load_overlays(&config.overlays, &timezone) } else { Vec::new() };
+            let unpublished = push_pending(&config);
+            if output == OutputFormat::Text {
+                list_events(&events, &overlay_events, past_days, future_days, date, &timezone, summary);
+                if unpublished {
+                    println!("{}", "⇡ unpublished changes, see plantrack push --status".dimmed());
+                }
+            } else {
+                print_list_output(&[events, overlay_events].concat(), past_days, future_days, date.as_deref(), &timezone, output, unpublished)?;
             }
         }
-        Commands::List { past_days, future_days, date, summary } => list_events(&events, past_days, future_days, date, &timezone, summary),
         // Commands::List { days } => list_events(&events, days),
-        Commands::Delete { id, timespan } => {
-            if delete_event(&mut events, &id, timespan, rounding, &timezone)? {
-                save_events(&schedule_file_path, &events)?;
-                generate_ics(&ics_file_path, &events, export_notes)?;
+        Commands::Delete { id, timespan, yes, output } => {
+            let original_events = events.clone();
+            delete_event(&mut events, &id, timespan.as_deref(), rounding, &timezone)?;
+            if output == OutputFormat::Text {
+                if timespan.is_some() {
+                    print_event_diff(&original_events, &events, &timezone);
+                } else if let Some(original_event) = original_events.iter().find(|event| event.id == id) {
+                    println!("{}", "Deleting the following event:".yellow().bold());
+                    println!("- {}", format_event_for_diff(original_event, &timezone).red());
+                }
+            }
+
+            let confirmed = yes || Confirm::with_theme(&ColorfulTheme::default())
+                .with_prompt(if timespan.is_some() { "Apply these changes?" } else { "Delete this event?" })
+                .interact()
+                .unwrap_or(false);
+
+            if !confirmed {
+                if output == OutputFormat::Text {
+                    println!("{}", if timespan.is_some() { "Changes not applied" } else { "Event not deleted" }.yellow());
+                }
+                print_changes(&original_events, &events, false, output, &timezone)?;
+                return Ok(ExitCode::from(EXIT_NOT_APPLIED));
+            }
+            save_schedule(&config, &original_events, &events, &timezone)?;
+            export_ics(&config, &events)?;
+            print_changes(&original_events, &events, true, output, &timezone)?;
+        }
+        Commands::Report { project, month, year, from, to, target, tag, by, output } => {
+            let parse_date = |date_str: &str| {
+                NaiveDate::parse_from_str(date_str, "%Y-%m-%d").map_err(|_| Error::new(ErrorKind::InvalidInput, "Invalid date format"))
+            };
+            let options = ReportOptions {
+                project: project.as_deref(),
+                tag: tag.as_deref(),
+                by,
+                month,
+                year,
+                from: from.as_deref().map(parse_date).transpose()?,
+                to: to.as_deref().map(parse_date).transpose()?,
+                target_time: target,
+                home_locations: &config.home_locations,
+            };
+            if output == OutputFormat::Text {
+                generate_report(&events, &options, &timezone, &config.billing)?;
+            } else {
+                print_report_output(&events, &options, &timezone, &config.billing, output)?;
+            }
+            // export_ics(&config, &events)?;
+        }
+        Commands::Timesheet { week, human, all, csv } => {
+            let monday = parse_week(week.as_deref().filter(|week| *week != "current"), &timezone)?;
+            let rows = generate_timesheet(&events, monday, &timezone, all);
+            print_timesheet(&rows, monday, &timezone, human);
+            if let Some(csv_path) = csv {
+                export_timesheet_csv(&csv_path, &rows, monday, human)?;
+            }
+        }
+        Commands::Balance { date, weeks, human } => {
+            let working_time = config.working_time.as_ref().ok_or_else(|| {
+                Error::new(ErrorKind::NotFound, "No working_time configured in the config file")
+            })?;
+            let until = match date {
+                Some(date_str) => NaiveDate::parse_from_str(&date_str, "%Y-%m-%d")
+                    .map_err(|_| Error::new(ErrorKind::InvalidInput, "Invalid date format"))?,
+                None => Utc::now().with_timezone(&timezone).date_naive(),
+            };
+            print_balance(&events, working_time, until, weeks, &timezone, human)?;
+        }
+        Commands::Mileage { month, year, from, to, commute, csv } => {
+            let parse_date = |date_str: &str| {
+                NaiveDate::parse_from_str(date_str, "%Y-%m-%d").map_err(|_| Error::new(ErrorKind::InvalidInput, "Invalid date format"))
+            };
+            let (from, to) = report_period(month, year, from.as_deref().map(parse_date).transpose()?, to.as_deref().map(parse_date).transpose()?, &timezone)?;
+            let trips = mileage_trips(&events, &config, &timezone, from, to, commute);
+            print_mileage(&trips, &config, from, to);
+            if let Some(csv_path) = csv {
+                export_mileage_csv(&csv_path, &trips, &config)?;
+            }
+        }
+        Commands::Stats { past_days, date, booked } => {
+            let until = match date {
+                Some(date_str) => NaiveDate::parse_from_str(&date_str, "%Y-%m-%d")
+                    .map_err(|_| Error::new(ErrorKind::InvalidInput, "Invalid date format"))?,
+                None => Utc::now().with_timezone(&timezone).date_naive(),
+            };
+            print_stats(&events, until, past_days, booked, &timezone);
+        }
+        Commands::Accuracy { weeks, date } => {
+            let last_week = parse_week(date.as_deref(), &timezone)?;
+            print_accuracy(&events, last_week, weeks, &timezone);
+        }
+        Commands::Chart { kind, date, weeks, all } => {
+            let until = match date {
+                Some(date_str) => NaiveDate::parse_from_str(&date_str, "%Y-%m-%d")
+                    .map_err(|_| Error::new(ErrorKind::InvalidInput, "Invalid date format"))?,
+                None => Utc::now().with_timezone(&timezone).date_naive(),
+            };
+            let width = terminal_width();
+            let totals = daily_totals(&events, &timezone, all);
+            if matches!(kind, ChartKind::All | ChartKind::Heatmap) {
+                print_heatmap(&totals, until, width);
+            }
+            if matches!(kind, ChartKind::All | ChartKind::Weeks) {
+                print_week_bars(&events, until, weeks, &timezone, all, width);
+            }
+            if matches!(kind, ChartKind::All | ChartKind::Sparkline) {
+                print_sparkline(&totals, until, width);
+            }
+        }
+        Commands::Export { format, from, to, output } => {
+            let parse_date = |date_str: &str| {
+                NaiveDate::parse_from_str(date_str, "%Y-%m-%d").map_err(|_| Error::new(ErrorKind::InvalidInput, "Invalid date format"))
+            };
+            let from = match from {
+                Some(date_str) => parse_date(&date_str)?,
+                None => Utc::now().with_timezone(&timezone).date_naive(),
+            };
+            let to = match to {
+                Some(date_str) => parse_date(&date_str)?,
+                None => from + Duration::days(7),
+            };
+            let mut agenda = agenda_events(&events, from, to, &timezone);
+            match format {
+                // Tasks are what is still to do, time trackers only know tracked time
+                ExportFormat::Todotxt => agenda.retain(|event| !event.booked && event.end_time > Utc::now()),
+                ExportFormat::Timewarrior | ExportFormat::Toggl | ExportFormat::Clockify | ExportFormat::Timeclock => agenda.retain(|event| event.booked),
+                ExportFormat::Org | ExportFormat::Markdown => {}
+            }
+            let content = match format {
+                ExportFormat::Org => export_org(&agenda, &timezone),
+                ExportFormat::Markdown => export_markdown(&agenda, &timezone),
+                ExportFormat::Todotxt => export_todotxt(&agenda, &timezone),
+                ExportFormat::Timewarrior => export_timewarrior(&agenda)?,
+                ExportFormat::Toggl | ExportFormat::Clockify => export_tracker_csv(&agenda, format, &timezone),
+                ExportFormat::Timeclock => export_timeclock(&agenda, &timezone),
+            };
+            match output {
+                Some(file_path) => {
+                    std::fs::write(&file_path, content)?;
+                    println!("{} events exported to {}", agenda.len(), file_path.display());
+                }
+                None => print!("{}", content),
             }
         }
-        Commands::Report { project, month, year, target } => {
-            generate_report(&events, &project, &timezone, month, year, target);
-            // generate_ics(&ics_file_path, &events, export_notes)?;
+        Commands::Import { format, file, yes } => {
+            let content = std::fs::read_to_string(&file)?;
+            let imported = match format {
+                ImportFormat::Timewarrior => parse_timewarrior(&content)?,
+                ImportFormat::Toggl | ImportFormat::Clockify => parse_tracker_csv(&content, &timezone)?,
+                ImportFormat::Timeclock => parse_timeclock(&content, &timezone)?,
+            };
+            // Events imported before are skipped, so an export can be imported again
+            let new_events: Vec<ScheduleEvent> = imported.into_iter().filter(|new| !is_imported(&events, new)).collect();
+            if new_events.is_empty() {
+                println!("{}", "No new events to import.".yellow());
+                return Ok(ExitCode::SUCCESS);
+            }
+
+            let original_events = events.clone();
+            let count = new_events.len();
+            for event in new_events {
+                insert_event(&mut events, event);
+            }
+            print_event_diff(&original_events, &events, &timezone);
+
+            if !yes && !Confirm::with_theme(&ColorfulTheme::default())
+                .with_prompt(format!("Import {} events?", count))
+                .interact()
+                .map_err(Error::other)?
+            {
+                println!("{}", "Events not imported".yellow());
+                return Ok(ExitCode::from(EXIT_NOT_APPLIED));
+            }
+            save_schedule(&config, &original_events, &events, &timezone)?;
+            export_ics(&config, &events)?;
+            println!("{}", format!("{} events imported", count).green());
         }
         Commands::Cleanup { days } => {
+            let original_events = events.clone();
             cleanup_events(&mut events, days);
-            save_events(&schedule_file_path, &events)?;
+            // Pruned events were not deleted, the event hooks are not told about them
+            run_pre_save(&config, &original_events, &events, &timezone)?;
+            save_events(&config.schedule_file, &events)?;
             println!("Cleaned up events older than {} days.", days);
-            generate_ics(&ics_file_path, &events, export_notes)?;
+            export_ics(&config, &events)?;
         }
-        Commands::Set { id, location, note, booked, timespan, date } => {
+        Commands::Set { id, location, note, booked, timespan, date, tags, untags, yes, output } => {
             let event_index = events.iter().position(|event| event.id == id).ok_or_else(|| {
                 Error::new(ErrorKind::NotFound, format!("Event with ID {} not found", id))
             })?;
@@ -1375,6 +5612,7 @@ fn main() -> Result<(), Error> {
 
             let mut modified_event = original_event.clone();
             let mut modified = false;
+            let note_changed = note.is_some();
 
             if let Some(location) = location {
                 if location.is_empty() {
@@ -1396,6 +5634,13 @@ fn main() -> Result<(), Error> {
                 modified_event.booked = booked;
                 modified = true;
             }
+            let note_tags = if note_changed { modified_event.note.as_deref() } else { None };
+            let mut new_tags = collect_tags(&modified_event.tags, &tags, note_tags);
+            new_tags.retain(|tag| !untags.iter().any(|untag| normalize_tag(untag) == *tag));
+            if new_tags != modified_event.tags {
+                modified_event.tags = new_tags;
+                modified = true;
+            }
 
             // Simplified date/time handling
             let (new_start_time, new_end_time) = if let Some(date_str) = date {
@@ -1418,64 +5663,152 @@ fn main() -> Result<(), Error> {
             }
 
 
-            if modified {
-                println!("{} {}", format!("Change event:").yellow().bold(), format_event_change_for_diff(&original_event, &modified_event, &timezone).yellow());
-                if Confirm::with_theme(&ColorfulTheme::default())
-                    .with_prompt("Apply these changes?")
-                    .interact()
-                    .unwrap()
-                {
-                    events.remove(event_index);
-                    split_overlapping_events(&mut events, modified_event, &timezone);
-                    save_events(&schedule_file_path, &events)?;
-                    generate_ics(&ics_file_path, &events, export_notes)?;
-                    println!("Event with ID {} modified", id.green().bold());
-                } else {
-                    println!("{}", "Changes not applied".yellow());
+            if !modified {
+                if output == OutputFormat::Text {
+                    println!("{}", "No changes specified for event".yellow());
                 }
+                print_changes(&events, &events, false, output, &timezone)?;
+                return Ok(ExitCode::from(EXIT_NOT_APPLIED));
+            }
+
+            let original_events = events.clone();
+            events.remove(event_index);
+            if output == OutputFormat::Text {
+                println!("{} {}", "Change event:".yellow().bold(), format_event_change_for_diff(&original_event, &modified_event, &timezone).yellow());
+                split_overlapping_events(&mut events, modified_event, &timezone);
             } else {
-                println!("{}", "No changes specified for event".yellow());
+                insert_event(&mut events, modified_event);
+            }
+
+            let confirmed = yes || Confirm::with_theme(&ColorfulTheme::default())
+                .with_prompt("Apply these changes?")
+                .interact()
+                .unwrap_or(false);
+
+            if !confirmed {
+                if output == OutputFormat::Text {
+                    println!("{}", "Changes not applied".yellow());
+                }
+                print_changes(&original_events, &events, false, output, &timezone)?;
+                return Ok(ExitCode::from(EXIT_NOT_APPLIED));
+            }
+            save_schedule(&config, &original_events, &events, &timezone)?;
+            export_ics(&config, &events)?;
+            if output == OutputFormat::Text {
+                println!("Event with ID {} modified", id.green().bold());
             }
+            print_changes(&original_events, &events, true, output, &timezone)?;
         }
-        Commands::Current {} => {
+        Commands::Current { output } => {
             let now = Utc::now().with_second(0).unwrap().with_nanosecond(0).unwrap().with_timezone(&timezone);
             let current_event = events.iter().find(|event| {
                 event.start_time <= now && now < event.end_time
             });
 
-            match current_event {
-                Some(event) => {
+            let unpublished = push_pending(&config);
+            let marker = if unpublished { " ⇡" } else { "" };
+            match (output, current_event) {
+                (OutputFormat::Text, Some(event)) => {
                     let (project, task) = event.summary.split_once(':').unwrap_or(("", &event.summary));
-                    println!("🗓 {project}:{task}");
+                    println!("🗓 {project}:{task}{marker}");
                 }
-                None => println!("🗓 No event"),
+                (OutputFormat::Text, None) => println!("🗓 No event{marker}"),
+                (OutputFormat::Json, _) => print_json(&CurrentOutput { event: current_event.map(|event| EventOutput::new(event, &timezone)), unpublished })?,
+                (OutputFormat::Tsv, _) => print_events_tsv(&current_event.into_iter().collect::<Vec<_>>(), &timezone),
+            }
+            // The text output is used in prompts and status bars, it always succeeds
+            if current_event.is_none() && output != OutputFormat::Text {
+                return Ok(ExitCode::from(EXIT_NEGATIVE));
             }
          },
-        Commands::Push {  } => {
-            generate_ics(&ics_file_path, &events, export_notes)?;
-            // Execute post-ICS command if configured
-            if let Some(command_str) = &config.push_command {
-                println!("Executing: {}", command_str);
-                let status = Command::new("sh")
-                    .arg("-c")
-                    .arg(command_str)
-                    .status()?;
-
-                if !status.success() {
-                    return Err(Error::new(
-                        ErrorKind::Other,
-                        format!("Command failed with exit code: {}", status),
-                    ));
-                }
+        Commands::Push { force: _, status: true } => print_push_status(&config, &events, &timezone)?,
+        Commands::Push { force, status: false } => {
+            export_ics(&config, &events)?;
+            push(&config, force, config.push_timeout.unwrap_or(60))?;
+        }
+        Commands::Sync { dry_run, prefer, yes } => {
+            let caldav = config.caldav.as_ref().ok_or_else(|| {
+                Error::new(ErrorKind::NotFound, "No caldav section in config file")
+            })?;
+            let client = CaldavClient::new(caldav)?;
+            let state_file_path = config.state_file();
+            let mut state = load_state(&state_file_path)?;
+            let window_start = Utc::now() - Duration::days(caldav.past_days.unwrap_or(7));
+
+            // The export rules apply to the synced calendar as well, unless it is private
+            let no_rules = BTreeMap::new();
+            let ics_options = match caldav.export_rules {
+                Some(false) => IcsOptions { rules: &no_rules, ..config.ics_options() },
+                _ => config.ics_options(),
+            };
+            let remote = client.fetch(window_start, &timezone)?;
+            let actions = plan_sync(&events, &remote, &state, window_start, prefer, &ics_options);
+            if actions.is_empty() {
+                println!("{}", "Already in sync.".green());
+                return Ok(ExitCode::SUCCESS);
+            }
+            print_sync_actions(&actions, &events, &timezone);
+            if dry_run {
+                return Ok(ExitCode::SUCCESS);
             }
+            if !yes && !Confirm::with_theme(&ColorfulTheme::default())
+                .with_prompt("Apply these changes?")
+                .interact()
+                .map_err(Error::other)?
+            {
+                println!("{}", "Sync aborted.".red());
+                return Ok(ExitCode::from(EXIT_NOT_APPLIED));
+            }
+
+            update_revisions(&mut state.ics, &events);
+            let original_events = events.clone();
+            // pre_save decides before anything is sent, uploads can't be taken back
+            apply_local_sync(&mut events, &actions);
+            run_pre_save(&config, &original_events, &events, &timezone)?;
+            let outcome = execute_sync(&client, actions, &mut state, &ics_options, window_start, &timezone);
+            write_schedule(&config, &original_events, &events, &timezone)?;
+            save_state(&state_file_path, &state)?;
+            export_ics(&config, &events)?;
+            if outcome.conflicts > 0 {
+                println!("{}", format!("{} conflicting changes were not synced, use --prefer local|remote to resolve them.", outcome.conflicts).red());
+            }
+            if outcome.failed > 0 {
+                println!("{}", format!("{} changes failed to sync, they are retried with the next sync.", outcome.failed).red());
+            }
+            if outcome.conflicts == 0 && outcome.failed == 0 {
+                println!("{}", "Sync complete.".green());
+            }
+        }
+        Commands::Completions { .. } | Commands::Complete { .. } => unreachable!("Completions are handled before loading the config"),
+        Commands::Serve { bind } => {
+            let credentials = config.serve.as_ref().and_then(|serve| serve.username.as_ref().map(|username| {
+                let credentials = format!("{}:{}", username, serve.password.as_deref().unwrap_or_default());
+                base64::engine::general_purpose::STANDARD.encode(credentials)
+            }));
+            serve(&bind, &ServeOptions {
+                config: &config,
+                credentials,
+            })?;
         }
-        Commands::Free { timespan, date } => {
+        Commands::Free { timespan, date, output } => {
             let (start_time, end_time) = parse_datetime_range(&timespan, date.as_deref(), rounding, &timezone)?;
 
             let start_time_local = start_time.with_timezone(&timezone);
             let end_time_local = end_time.with_timezone(&timezone);
 
-            match is_slot_free(&events, start_time, end_time) {
+            let busy_events: Vec<ScheduleEvent> = events
+                .iter()
+                .chain(&load_overlays(&config.overlays, &timezone))
+                .cloned()
+                .sorted_by_key(|event| event.start_time)
+                .collect();
+            let slot = is_slot_free(&busy_events, start_time, end_time);
+            let free = slot.is_ok();
+            if output != OutputFormat::Text {
+                print_free_output(start_time, end_time, slot.as_ref().err().map_or(&[], |conflicting_events| conflicting_events.as_slice()), &busy_events, &timezone, output)?;
+                return Ok(if free { ExitCode::SUCCESS } else { ExitCode::from(EXIT_NEGATIVE) });
+            }
+            match slot {
                 Ok(true) => {
                     println!("{}", format!("\nSlot {} - {} on {} is free", start_time_local.format("%H:%M"), end_time_local.format("%H:%M"), start_time_local.format("%Y-%m-%d")).green());
                 }
@@ -1484,7 +5817,10 @@ fn main() -> Result<(), Error> {
                     unreachable!();
                 },
                 Err(conflicting_events) => {
-                    if conflicting_events.iter().all(|e| !e.booked) {
+                    if conflicting_events.iter().all(|e| e.overlay.is_some()) {
+                        let overlays = conflicting_events.iter().filter_map(|e| e.overlay.as_deref()).unique().join(", ");
+                        println!("\n{}", format!("Slot {} - {} on {} is busy in {}", start_time_local.format("%H:%M"), end_time_local.format("%H:%M"), start_time_local.format("%Y-%m-%d"), overlays).red());
+                    } else if conflicting_events.iter().all(|e| !e.booked) {
                         println!("\n{}", format!("Slot {} - {} on {} is already planned", start_time_local.format("%H:%M"), end_time_local.format("%H:%M"), start_time_local.format("%Y-%m-%d")).yellow());
                     } else if conflicting_events.iter().any(|e| e.booked) {
                         println!("\n{}", format!("Slot {} - {} on {} is already booked", start_time_local.format("%H:%M"), end_time_local.format("%H:%M"), start_time_local.format("%Y-%m-%d")).red());
@@ -1492,7 +5828,7 @@ fn main() -> Result<(), Error> {
                         // Should not happen, but defaults to planned
                         println!("\n{}", format!("Slot {} - {} on {} is already planned", start_time_local.format("%H:%M"), end_time_local.format("%H:%M"), start_time_local.format("%Y-%m-%d")).yellow());
                     }
-                    println!("{}", format!("Conflicting event:").bright_red());
+                    println!("{}", "Conflicting event:".bright_red());
                     for conflicting_event in &conflicting_events {
                         print_event(conflicting_event, &timezone);
                     }
@@ -1500,7 +5836,7 @@ fn main() -> Result<(), Error> {
             }
             // Get all events for the specified date
             let date_naive = start_time_local.date_naive();
-            let events_for_day: Vec<&ScheduleEvent> = events
+            let events_for_day: Vec<&ScheduleEvent> = busy_events
                 .iter()
                 .filter(|event| event.start_time.with_timezone(&timezone).date_naive() == date_naive)
                 .collect();
@@ -1518,9 +5854,9 @@ fn main() -> Result<(), Error> {
                     if let Some(last_et) = last_end_time {
                         let free_time = start_time_local - last_et;
                         if free_time > Duration::zero() {
-                            println!("                               {}", format!("⋮").bright_green());
+                            println!("                               {}", "⋮".bright_green());
                             println!("                               {} {}", format_duration(free_time, true).bright_green(), "free".bright_green());
-                            println!("                               {}", format!("⋮").bright_green());
+                            println!("                               {}", "⋮".bright_green());
                         }
                     }
 
@@ -1528,9 +5864,366 @@ fn main() -> Result<(), Error> {
                     last_end_time = Some(event.end_time.with_timezone(&timezone));
                 }
             }
+            if !free {
+                return Ok(ExitCode::from(EXIT_NEGATIVE));
+            }
         }
     }
 
-    // generate_ics(&ics_file_path, &events, export_notes)?;
-    Ok(())
+    // export_ics(&config, &events)?;
+    if mutating {
+        retry_push(&config, config.auto_push);
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn time(value: &str) -> DateTime<Utc> {
+        chrono::NaiveDateTime::parse_from_str(value, "%Y-%m-%d %H:%M").unwrap().and_utc()
+    }
+
+    fn event(summary: &str, start: &str, end: &str, booked: bool) -> ScheduleEvent {
+        ScheduleEvent {
+            id: Uuid::new_v4().to_string(),
+            start_time: time(start),
+            end_time: time(end),
+            summary: summary.to_string(),
+            note: None,
+            location: None,
+            booked,
+            tags: Vec::new(),
+            planned_ahead: None,
+            overlay: None,
+        }
+    }
+
+    #[test]
+    fn parse_csv_handles_quotes_and_line_endings() {
+        let content = "\u{feff}Project,Description\r\n\"A, B\",\"say \"\"hi\"\"\nthere\"\r\n\r\nC,\n";
+        assert_eq!(parse_csv(content), [vec!["Project", "Description"], vec!["A, B", "say \"hi\"\nthere"], vec!["C", ""]]);
+    }
+
+    #[test]
+    fn parse_tracker_csv_maps_columns() {
+        let content = "Client,Project,Task,Description,Billable,Start date,Start time,End date,End time,Tags\n\
+            Acme,Website,Review,Check the PR,Yes,2024-01-01,09:00:00,2024-01-01,10:30:00,\"remote, support\"\n\
+            Acme,,,Call,No,01/02/2024,02:00 PM,01/02/2024,03:00 PM,\n";
+        let events = parse_tracker_csv(content, &chrono_tz::Europe::Berlin).unwrap();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].summary, "Website:Review");
+        assert_eq!(events[0].note.as_deref(), Some("Check the PR"));
+        assert_eq!(events[0].tags, ["billable", "remote", "support"]);
+        assert_eq!((events[0].start_time, events[0].end_time), (time("2024-01-01 08:00"), time("2024-01-01 09:30")));
+        assert_eq!(events[1].summary, "Acme:Call");
+        assert_eq!(events[1].start_time, time("2024-01-02 13:00"));
+
+        assert!(parse_tracker_csv("Project,Start date\nA,2024-01-01\n", &Tz::UTC).is_err());
+    }
+
+    #[test]
+    fn timewarrior_round_trip() {
+        let mut work = event("A:b", "2024-01-01 09:00", "2024-01-01 10:00", true);
+        work.tags = vec!["remote".to_string()];
+        work.note = Some("notes".to_string());
+        let exported = export_timewarrior(&[&work]).unwrap();
+        let imported = parse_timewarrior(&exported).unwrap();
+        assert_eq!(imported.len(), 1);
+        assert_eq!((imported[0].start_time, imported[0].end_time), (work.start_time, work.end_time));
+        assert_eq!((imported[0].summary.as_str(), imported[0].note.as_deref(), imported[0].tags.clone()), ("A:b", Some("notes"), work.tags));
+
+        let data = "inc 20240101T090000Z - 20240101T100000Z # A:b remote # \"notes\"\n";
+        assert_eq!(parse_timewarrior(data).unwrap()[0].summary, "A:b");
+    }
+
+    #[test]
+    fn timeclock_round_trip() {
+        let mut work = event("ClientA:Website", "2024-11-18 08:00", "2024-11-18 10:00", true);
+        work.note = Some("deploy; fix  layout".to_string());
+        work.tags = vec!["billable".to_string(), "remote".to_string()];
+        let exported = export_timeclock(&[&work], &chrono_tz::Europe::Berlin);
+        assert_eq!(exported, "i 2024/11/18 09:00:00 ClientA:Website  deploy, fix  layout  ; billable:, remote:\no 2024/11/18 11:00:00\n");
+
+        let imported = parse_timeclock(&exported, &chrono_tz::Europe::Berlin).unwrap();
+        assert_eq!(imported.len(), 1);
+        assert_eq!((imported[0].start_time, imported[0].end_time), (work.start_time, work.end_time));
+        assert_eq!(imported[0].summary, work.summary);
+        assert_eq!(imported[0].note.as_deref(), Some("deploy, fix  layout"));
+        assert_eq!(imported[0].tags, work.tags);
+
+        assert!(parse_timeclock("i 2024/11/18 09:00:00 A:b\ni 2024/11/18 10:00:00 A:c\no 2024/11/18 11:00:00\n", &Tz::UTC).is_err());
+        assert!(parse_timeclock("o 2024/11/18 11:00:00\n", &Tz::UTC).is_err());
+    }
+
+    #[test]
+    fn newly_booked_counts_extended_bookings() {
+        let mut events = vec![event("A:b", "2024-01-01 09:00", "2024-01-01 10:00", true), event("A:c", "2024-01-01 13:00", "2024-01-01 14:00", false)];
+        let before = events.clone();
+        insert_event(&mut events, event("A:b", "2024-01-01 10:00", "2024-01-01 11:00", true));
+        events[1].booked = true;
+        let changes = event_changes(&before, &events, true, &Tz::UTC);
+        assert!(changes.added.is_empty());
+        let booked: Vec<(&str, i64)> = newly_booked(&changes).iter().map(|booked| (booked.event.summary, booked.booked_minutes)).collect();
+        assert_eq!(booked, [("A:b", 60), ("A:c", 60)]);
+
+        // Shortening a booked event books nothing
+        let before = events.clone();
+        events[0].end_time = time("2024-01-01 10:30");
+        assert!(newly_booked(&event_changes(&before, &events, true, &Tz::UTC)).is_empty());
+    }
+
+    #[test]
+    fn imported_events_are_recognized_after_merging() {
+        let imported = [event("A:b", "2024-01-01 09:00", "2024-01-01 10:00", true), event("A:b", "2024-01-01 10:00", "2024-01-01 11:00", true)];
+        let mut events = Vec::new();
+        for event in imported.clone() {
+            insert_event(&mut events, event);
+        }
+        assert_eq!(events.len(), 1);
+        assert!(imported.iter().all(|event| is_imported(&events, event)));
+        assert!(!is_imported(&events, &event("A:c", "2024-01-01 09:00", "2024-01-01 10:00", true)));
+        assert!(!is_imported(&events, &event("A:b", "2024-01-01 10:30", "2024-01-01 11:30", true)));
+    }
+
+    #[test]
+    fn booking_exactly_over_a_plan_keeps_the_uid() {
+        let plan = event("A:b", "2024-01-01 09:00", "2024-01-01 10:00", false);
+        let mut events = vec![plan.clone(), event("C:d", "2024-01-01 10:00", "2024-01-01 11:00", false)];
+        insert_event(&mut events, event("A:b", "2024-01-01 09:00", "2024-01-01 10:00", true));
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].id, plan.id);
+        assert!(events[0].booked);
+
+        // Another task's event isn't passed on
+        let covered = events[1].clone();
+        insert_event(&mut events, event("A:b", "2024-01-01 10:00", "2024-01-01 11:00", false));
+        assert!(events.iter().all(|event| event.id != covered.id));
+    }
+
+    #[test]
+    fn booking_over_a_plan_counts_as_planned() {
+        let mut events = vec![event("A:b", "2024-01-01 09:00", "2024-01-01 10:00", false), event("C:d", "2024-01-01 10:00", "2024-01-01 11:00", false)];
+        let mut booked = event("A:b", "2024-01-01 09:00", "2024-01-01 10:00", true);
+        booked.planned_ahead = Some(false);
+        insert_event(&mut events, booked);
+        assert_eq!(events[0].planned_ahead, Some(true));
+
+        // A plan of another task doesn't count
+        let mut booked = event("A:b", "2024-01-01 10:00", "2024-01-01 11:00", true);
+        booked.planned_ahead = Some(false);
+        insert_event(&mut events, booked);
+        assert_eq!(events.iter().filter(|event| event.planned_ahead == Some(false)).count(), 1);
+
+        // Only the part over the plan is planned
+        let plan = event("E:f", "2024-01-02 09:00", "2024-01-02 10:00", false);
+        let mut events = vec![plan.clone()];
+        let mut booked = event("E:f", "2024-01-02 08:00", "2024-01-02 12:00", true);
+        booked.planned_ahead = Some(false);
+        insert_event(&mut events, booked);
+        let parts: Vec<_> = events.iter().map(|event| (event.start_time, event.end_time, event.planned_ahead, event.booked)).collect();
+        assert_eq!(parts, [
+            (time("2024-01-02 08:00"), time("2024-01-02 09:00"), Some(false), true),
+            (time("2024-01-02 09:00"), time("2024-01-02 10:00"), Some(true), true),
+            (time("2024-01-02 10:00"), time("2024-01-02 12:00"), Some(false), true),
+        ]);
+        assert_eq!(events[1].id, plan.id);
+    }
+
+    #[test]
+    fn billed_time_rounds_per_policy_and_skips_planned_events() {
+        let billing = HashMap::from([(
+            "A".to_string(),
+            BillingPolicy { increment: 15, aggregate: BillingAggregate::Day, rounding: BillingRounding::Up },
+        )]);
+        let events = [
+            event("A:b", "2024-01-01 09:00", "2024-01-01 09:10", true),
+            event("A:b", "2024-01-01 10:00", "2024-01-01 10:10", true),
+            event("A:b", "2024-01-02 09:00", "2024-01-02 12:00", false),
+            event("B:c", "2024-01-01 11:00", "2024-01-01 11:07", true),
+        ];
+        let events: Vec<&ScheduleEvent> = events.iter().collect();
+        // 20 minutes on one day round up to 30, B has no policy and is billed as is
+        assert_eq!(billed_time(&events, &billing, &Tz::UTC), Duration::minutes(37));
+
+        let per_event = BillingPolicy { increment: 6, aggregate: BillingAggregate::Event, rounding: BillingRounding::Nearest };
+        assert_eq!(per_event.bill_events(&events[..3], &Tz::UTC), Duration::minutes(24));
+    }
+
+    fn remote(event: &ScheduleEvent, etag: &str) -> (String, Option<String>, IcsEvent) {
+        let ics_event = IcsEvent {
+            uid: event.id.clone(),
+            start_time: event.start_time,
+            end_time: event.end_time,
+            summary: event.summary.clone(),
+            status: Some("CONFIRMED".to_string()),
+            location: None,
+            description: None,
+            categories: Vec::new(),
+            zone: Tz::UTC,
+            rrule: None,
+            exdates: Vec::new(),
+            recurrence_id: None,
+        };
+        (format!("{}.ics", event.id), Some(etag.to_string()), ics_event)
+    }
+
+    fn ics_options(rules: &BTreeMap<String, ExportRule>) -> IcsOptions<'_> {
+        IcsOptions {
+            calendar_name: APP_NAME,
+            timezone: None,
+            export_notes: true,
+            summary_depth: 1,
+            rules,
+            past_days: 7,
+            future_days: None,
+            projects: &[],
+            tags: &[],
+        }
+    }
+
+    fn synced(state: &mut State, event: &ScheduleEvent, etag: &str, options: &IcsOptions) {
+        let entry = SyncEntry { href: format!("{}.ics", event.id), etag: Some(etag.to_string()), hash: sync_hash(event, options), end_time: event.end_time };
+        state.caldav.insert(event.id.clone(), entry);
+    }
+
+    #[test]
+    fn plan_sync_detects_changes_on_both_sides() {
+        let window_start = time("2024-01-01 00:00");
+        let new_local = event("A:new", "2024-01-02 09:00", "2024-01-02 10:00", true);
+        let unchanged = event("A:same", "2024-01-02 10:00", "2024-01-02 11:00", true);
+        let changed_remotely = event("A:remote", "2024-01-02 11:00", "2024-01-02 12:00", true);
+        let mut changed_locally = event("A:local", "2024-01-02 12:00", "2024-01-02 13:00", true);
+        let mut changed_both = event("A:both", "2024-01-02 13:00", "2024-01-02 14:00", true);
+        let deleted_locally = event("A:deleted", "2024-01-02 14:00", "2024-01-02 15:00", true);
+        let new_remote = event("A:new remote", "2024-01-02 15:00", "2024-01-02 16:00", true);
+
+        let no_rules = BTreeMap::new();
+        let mut state = State::default();
+        for event in [&unchanged, &changed_remotely, &changed_locally, &changed_both, &deleted_locally] {
+            synced(&mut state, event, "1", &ics_options(&no_rules));
+        }
+        changed_locally.note = Some("changed".to_string());
+        changed_both.note = Some("changed".to_string());
+
+        let events = vec![new_local.clone(), unchanged.clone(), changed_remotely.clone(), changed_locally.clone(), changed_both.clone()];
+        let remote_events = vec![
+            remote(&unchanged, "1"),
+            remote(&changed_remotely, "2"),
+            remote(&changed_locally, "1"),
+            remote(&changed_both, "2"),
+            remote(&deleted_locally, "1"),
+            remote(&new_remote, "1"),
+        ];
+
+        let actions = plan_sync(&events, &remote_events, &state, window_start, None, &ics_options(&no_rules));
+        let summary: Vec<String> = actions
+            .iter()
+            .map(|action| match action {
+                SyncAction::Upload(event, _) => format!("upload {}", event.summary),
+                SyncAction::DeleteRemote(uid, _) => format!("delete remote {}", if *uid == deleted_locally.id { "A:deleted" } else { uid }),
+                SyncAction::Import(event, _, _) => format!("import {}", event.summary),
+                SyncAction::DeleteLocal(event) => format!("delete local {}", event.summary),
+                SyncAction::Conflict(event, _) => format!("conflict {}", event.summary),
+            })
+            .collect();
+        assert_eq!(summary, ["upload A:new", "import A:remote", "upload A:local", "conflict A:both", "delete remote A:deleted", "import A:new remote"]);
+
+        // With a preference the conflict is resolved
+        let actions = plan_sync(&events, &remote_events, &state, window_start, Some(SyncPrefer::Local), &ics_options(&no_rules));
+        assert!(actions.iter().any(|action| matches!(action, SyncAction::Upload(event, _) if event.id == changed_both.id)));
+    }
+
+    #[test]
+    fn plan_sync_applies_export_rules() {
+        let window_start = time("2024-01-01 00:00");
+        let hidden = event("Internal:admin", "2024-01-02 09:00", "2024-01-02 10:00", true);
+        let mut renamed = event("ClientB:call", "2024-01-02 10:00", "2024-01-02 11:00", true);
+        renamed.note = Some("private".to_string());
+        renamed.location = Some("Office".to_string());
+        let rules = BTreeMap::from([
+            ("Internal".to_string(), ExportRule { export: Some(false), ..Default::default() }),
+            ("ClientB".to_string(), ExportRule { summary: Some("Client meeting".to_string()), notes: Some(false), location: Some(false), ..Default::default() }),
+        ]);
+        let mut state = State::default();
+        synced(&mut state, &hidden, "1", &ics_options(&rules));
+        synced(&mut state, &renamed, "1", &ics_options(&rules));
+        // A change of a hidden field is not uploaded
+        renamed.note = Some("private, changed".to_string());
+        // The calendar client moved the meeting, it only knows the shared fields
+        let mut moved = remote(&renamed, "2");
+        moved.2.summary = "Client meeting".to_string();
+        moved.2.start_time = time("2024-01-02 11:00");
+        moved.2.end_time = time("2024-01-02 12:00");
+        let remote_events = vec![remote(&hidden, "1"), moved];
+
+        let actions = plan_sync(&[hidden.clone(), renamed.clone()], &remote_events, &state, window_start, None, &ics_options(&rules));
+        assert_eq!(actions.len(), 2);
+        assert!(matches!(&actions[0], SyncAction::Import(event, _, _)
+            if event.summary == "ClientB:call" && event.note.as_deref() == Some("private, changed") && event.location.as_deref() == Some("Office") && event.start_time == time("2024-01-02 11:00")));
+        assert!(matches!(&actions[1], SyncAction::DeleteRemote(uid, _) if *uid == hidden.id));
+    }
+
+    fn recurring(start: &str, rrule: &str, zone: Tz) -> IcsEvent {
+        let mut ics_event = remote(&event("Meeting", start, start, true), "1").2;
+        ics_event.end_time = ics_event.start_time + Duration::hours(1);
+        ics_event.rrule = Some(rrule.to_string());
+        ics_event.zone = zone;
+        ics_event
+    }
+
+    fn starts(occurrences: Option<Vec<(DateTime<Utc>, DateTime<Utc>)>>) -> Vec<String> {
+        occurrences.unwrap().iter().map(|(start, _)| start.format("%Y-%m-%d %H:%M").to_string()).collect()
+    }
+
+    #[test]
+    fn expand_recurrence_weekly_monthly_and_exdates() {
+        let until = time("2025-01-01 00:00");
+        let mut weekly = recurring("2024-01-01 09:00", "FREQ=WEEKLY;BYDAY=MO,WE;COUNT=4", Tz::UTC);
+        assert_eq!(starts(expand_recurrence(&weekly, until)), ["2024-01-01 09:00", "2024-01-03 09:00", "2024-01-08 09:00", "2024-01-10 09:00"]);
+        // Excluded occurrences still count
+        weekly.exdates = vec![time("2024-01-03 09:00")];
+        assert_eq!(starts(expand_recurrence(&weekly, until)), ["2024-01-01 09:00", "2024-01-08 09:00", "2024-01-10 09:00"]);
+
+        // Months without a 31st are skipped
+        let monthly = recurring("2024-01-31 09:00", "FREQ=MONTHLY;COUNT=3", Tz::UTC);
+        assert_eq!(starts(expand_recurrence(&monthly, until)), ["2024-01-31 09:00", "2024-03-31 09:00", "2024-05-31 09:00"]);
+
+        // Local time is kept across the DST change
+        let daily = recurring("2024-03-30 08:00", "FREQ=DAILY;UNTIL=20240331T235959Z", chrono_tz::Europe::Berlin);
+        assert_eq!(starts(expand_recurrence(&daily, until)), ["2024-03-30 08:00", "2024-03-31 07:00"]);
+    }
+
+    #[test]
+    fn expand_recurrence_rejects_unsupported_rules() {
+        let until = time("2025-01-01 00:00");
+        for rrule in ["FREQ=MONTHLY;BYDAY=1MO", "FREQ=MONTHLY;BYMONTHDAY=15", "FREQ=MONTHLY;BYDAY=MO,TU;BYSETPOS=-1", "FREQ=WEEKLY;BYDAY=1MO", "FREQ=HOURLY", "FREQ=WEEKLY;INTERVAL=2;BYDAY=MO,SU;WKST=SU"] {
+            assert!(expand_recurrence(&recurring("2024-01-01 09:00", rrule, Tz::UTC), until).is_none(), "{}", rrule);
+        }
+        assert!(expand_recurrence(&recurring("2024-01-01 09:00", "FREQ=WEEKLY;WKST=SU;BYDAY=MO", Tz::UTC), until).is_some());
+    }
+
+    #[test]
+    fn serve_checks_basic_authentication() {
+        let credentials = base64::engine::general_purpose::STANDARD.encode("user:secret");
+        assert!(is_authorized(Some(&format!("Basic {}", credentials)), &credentials));
+        assert!(is_authorized(Some(&format!("basic  {}", credentials)), &credentials));
+        assert!(!is_authorized(Some(&format!("Bearer {}", credentials)), &credentials));
+        assert!(!is_authorized(Some("Basic dXNlcjpzZWNyZXQy"), &credentials));
+        assert!(!is_authorized(None, &credentials));
+    }
+
+    #[test]
+    fn agenda_exports_keep_notes_in_their_event() {
+        let mut todo = event("A:b", "2099-01-01 09:00", "2099-01-01 10:00", false);
+        todo.note = Some("first\r\n* second\n".to_string());
+        let org = export_org(&[&todo], &Tz::UTC);
+        assert!(org.ends_with("   :END:\n   first\n   * second\n"));
+        assert_eq!(org.lines().filter(|line| line.starts_with('*')).count(), 2);
+        assert!(export_markdown(&[&todo], &Tz::UTC).contains(" - first * second\n"));
+        let todotxt = export_todotxt(&[&todo], &Tz::UTC);
+        assert_eq!(todotxt.lines().count(), 1);
+        assert!(todotxt.starts_with("b +A first * second due:2099-01-01"));
+    }
 }